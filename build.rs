@@ -0,0 +1,24 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Reads the pinned version of a dependency from the manifest, which is the only file shipped with the sources.
+fn pinned_version(manifest: &str, name: &str) -> Option<String> {
+    let line = manifest.lines()
+        .find(|line| line.starts_with(&format!("{name} =")))?;
+    let start = line.find("\"=")? + 2;
+    let end = start + line[start..].find('"')?;
+
+    Some(line[start..end].to_owned())
+}
+
+fn main() {
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let manifest = fs::read_to_string(Path::new(&dir).join("Cargo.toml")).unwrap_or_default();
+
+    for (name, key) in [("gif", "GIF_VERSION"), ("skia-safe", "SKIA_SAFE_VERSION")] {
+        let version = pinned_version(&manifest, name).unwrap_or_else(|| String::from("unknown"));
+        println!("cargo:rustc-env={key}={version}");
+    }
+    println!("cargo:rerun-if-changed=Cargo.toml");
+}
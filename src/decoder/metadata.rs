@@ -0,0 +1,129 @@
+pub const XMP_IDENTIFIER: &[u8; 11] = b"XMP DataXMP";
+
+/// Follows the XMP packet so that readers walking sub-blocks land on the block terminator
+/// whichever byte of the packet they start from.
+pub fn xmp_trailer() -> impl Iterator<Item = u8> {
+    [0x01].into_iter().chain((0..=0xFF).rev())
+}
+
+const XMP_TRAILER_LEN: usize = 257;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metadata {
+    Comment(Vec<u8>),
+    /// `identifier` holds the 8 byte application identifier followed by the 3 byte authentication code.
+    Application { identifier: Vec<u8>, data: Vec<u8> },
+    Xmp(Vec<u8>),
+}
+
+fn sub_blocks<'a>(bytes: &'a [u8], position: &mut usize) -> Option<Vec<&'a [u8]>> {
+    let mut blocks = Vec::new();
+    loop {
+        let len = *bytes.get(*position)? as usize;
+        *position += 1;
+        if len == 0 {
+            return Some(blocks);
+        }
+        blocks.push(bytes.get(*position..*position + len)?);
+        *position += len;
+    }
+}
+
+fn color_table(flags: u8) -> usize {
+    if flags & 0x80 != 0 { 3 << ((flags & 0x07) + 1) } else { 0 }
+}
+
+fn application(blocks: &[&[u8]]) -> Option<Metadata> {
+    let (identifier, blocks) = blocks.split_first()?;
+    if *identifier == XMP_IDENTIFIER {
+        // the packet is raw bytes, its sub-block lengths are part of the text
+        let mut raw: Vec<u8> = blocks.iter()
+            .flat_map(|block| [block.len() as u8].into_iter().chain(block.iter().copied()))
+            .collect();
+        raw.truncate(raw.len().saturating_sub(XMP_TRAILER_LEN));
+        return Some(Metadata::Xmp(raw));
+    }
+
+    Some(Metadata::Application {
+        identifier: identifier.to_vec(),
+        data: blocks.concat(),
+    })
+}
+
+/// Collects comment and application extensions in file order, keeping whatever was read
+/// before the stream ends or turns malformed.
+pub fn metadata(bytes: &[u8]) -> Vec<Metadata> {
+    let mut list = Vec::new();
+    if bytes.len() < 13 || &bytes[..3] != b"GIF" {
+        return list;
+    }
+    let mut position = 13 + color_table(bytes[10]);
+
+    while let Some(&block) = bytes.get(position) {
+        position += 1;
+        match block {
+            0x21 => {
+                let label = match bytes.get(position) {
+                    Some(&label) => label,
+                    None => break,
+                };
+                position += 1;
+                let blocks = match sub_blocks(bytes, &mut position) {
+                    Some(blocks) => blocks,
+                    None => break,
+                };
+                match label {
+                    0xFE => list.push(Metadata::Comment(blocks.concat())),
+                    0xFF => list.extend(application(&blocks)),
+                    _ => {}
+                }
+            }
+            0x2C => {
+                let flags = match bytes.get(position + 8) {
+                    Some(&flags) => flags,
+                    None => break,
+                };
+                // descriptor, local color table and the LZW minimum code size
+                position += 9 + color_table(flags) + 1;
+                if sub_blocks(bytes, &mut position).is_none() {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+
+    list
+}
+
+#[test]
+fn metadata_round_trip() {
+    use gif::*;
+    use crate::encoder::writer::Writer;
+
+    let (width, height) = (8u16, 8u16);
+    let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF/></x:xmpmeta>".repeat(9);
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let comment = "source: https://example.org/ ".repeat(12);
+
+    let mut writer = Writer::new(Vec::new(), width, height, &[0, 0, 0, 255, 255, 255]).unwrap();
+    writer.encoder.set_repeat(Repeat::Infinite).unwrap();
+    writer.write_comment(&comment).unwrap();
+    writer.write_application(b"EXAMPLE1234", &data).unwrap();
+    writer.write_xmp(&xmp).unwrap();
+    writer.write_frame(&Frame::from_indexed_pixels(width, height, &[1; 64], None)).unwrap();
+    writer.write_comment("").unwrap();
+    let output = writer.encoder.into_inner().unwrap();
+
+    assert_eq!(metadata(&output), [
+        Metadata::Application { identifier: b"NETSCAPE2.0".to_vec(), data: vec![1, 0, 0] },
+        Metadata::Comment(comment.into_bytes()),
+        Metadata::Application { identifier: b"EXAMPLE1234".to_vec(), data },
+        Metadata::Xmp(xmp.into_bytes()),
+    ]);
+
+    // the extensions must not disturb an ordinary decoder
+    let mut decoder = DecodeOptions::new().read_info(output.as_slice()).unwrap();
+    assert!(decoder.read_next_frame().unwrap().is_some());
+    assert!(decoder.read_next_frame().unwrap().is_none());
+}
@@ -0,0 +1,91 @@
+use std::io::{self, Cursor};
+use gif::*;
+use crate::encoder::interlace::deinterlace;
+use super::metadata::*;
+
+/// Holds the whole file, extensions are collected up front since the `gif` decoder skips them.
+pub struct Reader {
+    pub decoder: Decoder<Cursor<Vec<u8>>>,
+    pub metadata: Vec<Metadata>,
+}
+
+impl Reader {
+    pub fn new(bytes: Vec<u8>) -> Result<Self, DecodingError> {
+        let metadata = metadata(&bytes);
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+
+        Ok(Reader {
+            decoder: options.read_info(Cursor::new(bytes))?,
+            metadata,
+        })
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = &[u8]> {
+        self.metadata.iter().filter_map(|metadata| match metadata {
+            Metadata::Comment(text) => Some(text.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn xmp(&self) -> Option<&[u8]> {
+        self.metadata.iter().find_map(|metadata| match metadata {
+            Metadata::Xmp(xmp) => Some(xmp.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn applications(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.metadata.iter().filter_map(|metadata| match metadata {
+            Metadata::Application { identifier, data } => Some((identifier.as_slice(), data.as_slice())),
+            _ => None,
+        })
+    }
+
+    /// Reads the next frame with its buffer in display order. Unlike the `gif` decoder,
+    /// the interlace flag is kept, so writing the frame again keeps it interlaced.
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'static>>, DecodingError> {
+        let mut frame = match self.decoder.next_frame_info()? {
+            Some(frame) => frame.clone(),
+            None => return Ok(None),
+        };
+
+        let mut buffer = vec![0; self.decoder.buffer_size()];
+        if !self.decoder.fill_buffer(&mut buffer)? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "image truncated").into());
+        }
+        if frame.interlaced {
+            buffer = deinterlace(&buffer, frame.width as _, frame.height as _);
+        }
+        frame.buffer = buffer.into();
+
+        Ok(Some(frame))
+    }
+}
+
+#[test]
+fn reader_interlaced() {
+    use crate::encoder::writer::Writer;
+
+    let (width, height) = (24u16, 18u16);
+    let palette: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, 0, 255 - i * 16]).collect();
+    let pixels: Vec<u8> = (0..width as u32 * height as u32)
+        .map(|i| ((i / width as u32 + i % 3) % 16) as u8)
+        .collect();
+
+    let mut writer = Writer::new(Vec::new(), width, height, &palette).unwrap();
+    let frame = Frame::from_indexed_pixels(width, height, &pixels, None);
+    writer.interlaced = true;
+    writer.write_frame(&frame).unwrap();
+    writer.interlaced = false;
+    writer.write_frame(&frame).unwrap();
+    let output = writer.encoder.into_inner().unwrap();
+
+    let mut reader = Reader::new(output).unwrap();
+    for interlaced in [true, false] {
+        let decoded = reader.next_frame().unwrap().unwrap();
+        assert_eq!(decoded.interlaced, interlaced);
+        assert_eq!(decoded.buffer.as_ref(), pixels.as_slice());
+    }
+    assert!(reader.next_frame().unwrap().is_none());
+}
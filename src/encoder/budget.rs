@@ -0,0 +1,242 @@
+use gif::*;
+use super::lossy::lossy_frame;
+use super::palette::remap;
+use super::quantizer::*;
+use super::writer::Writer;
+
+type RGBA = [u8; 4];
+
+type RGB = [u8; 3];
+
+const COLORS: [usize; 6] = [256, 192, 128, 96, 64, 32];
+
+const LOSSY: [u32; 5] = [0, 20, 40, 60, 80];
+
+const SCALES: [f32; 7] = [1.0, 0.9, 0.8, 0.7, 0.6, 0.5, 0.4];
+
+const STEPS: [usize; 4] = [1, 2, 3, 4];
+
+pub struct Source {
+    pub pixels: Vec<RGBA>,
+    pub width: u16,
+    pub height: u16,
+    pub delay: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub colors: usize,
+    pub lossy: u32,
+    pub scale: f32,
+    pub step: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            colors: COLORS[0],
+            lossy: LOSSY[0],
+            scale: SCALES[0],
+            step: STEPS[0],
+        }
+    }
+}
+
+/// Scales straight alpha pixels from the first size to the second, `None` when the scaler fails.
+pub type Resample = fn(&[RGBA], (usize, usize), (usize, usize)) -> Option<Vec<RGBA>>;
+
+pub struct Budget {
+    pub width: u16,
+    pub height: u16,
+    pub limit: usize,
+    pub repeat: Repeat,
+    pub resample: Resample,
+    pub frames: Vec<Source>,
+}
+
+// region Frame Reduce
+
+fn scaled(value: u16, scale: f32) -> u16 {
+    ((value as f32 * scale).round() as u16).max(1)
+}
+
+fn palette_frame(pixels: &[RGBA], width: u16, height: u16, colors: usize) -> Frame<'static> {
+    let transparent = pixels.iter().any(|pixel| pixel[3] < 0x80);
+    let opaque: Vec<RGBA> = pixels.iter()
+        .filter(|pixel| pixel[3] >= 0x80)
+        .copied()
+        .collect();
+
+    // the transparent index takes the last slot of the palette
+    let mut palette: Vec<RGB> = if transparent {
+        octtree_quantizer(&opaque, colors - 1, PaletteSort::None, 0)
+    } else {
+        octtree_quantizer(&opaque, colors, PaletteSort::None, 0)
+    };
+    if palette.is_empty() {
+        palette.push([0, 0, 0]);
+    }
+
+    let mut indexed = remap(pixels, &palette);
+    let index = if transparent {
+        let index = palette.len() as u8;
+        for (pixel, color) in indexed.iter_mut().zip(pixels) {
+            if color[3] < 0x80 {
+                *pixel = index;
+            }
+        }
+        palette.push([0, 0, 0]);
+        Some(index)
+    } else {
+        None
+    };
+
+    let mut frame = Frame::from_palette_pixels(width, height, &indexed, &palette.concat(), index);
+    if transparent {
+        frame.dispose = DisposalMethod::Background;
+    }
+    frame
+}
+
+// endregion
+
+impl Budget {
+    pub fn new(width: u16, height: u16, limit: usize, repeat: Repeat, resample: Resample) -> Self {
+        Budget {
+            width,
+            height,
+            limit,
+            repeat,
+            resample,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, pixels: &[RGBA], width: u16, height: u16, delay: u16) {
+        // frames larger than the canvas are cropped to it
+        let (cropped_width, cropped_height) = (width.min(self.width), height.min(self.height));
+        if cropped_width == 0 || cropped_height == 0 {
+            return;
+        }
+        let pixels = pixels.chunks(width as usize)
+            .take(cropped_height as usize)
+            .flat_map(|row| &row[..cropped_width as usize])
+            .copied()
+            .collect();
+
+        self.frames.push(Source { pixels, width: cropped_width, height: cropped_height, delay });
+    }
+
+    pub fn encode(&self, settings: &Settings) -> Result<Vec<u8>, EncodingError> {
+        let mut writer = Writer::new(
+            Vec::new(),
+            scaled(self.width, settings.scale),
+            scaled(self.height, settings.scale),
+            &[],
+        )?;
+        writer.encoder.set_repeat(self.repeat)?;
+
+        for group in self.frames.chunks(settings.step) {
+            let source = &group[0];
+            let width = scaled(source.width, settings.scale).min(writer.width);
+            let height = scaled(source.height, settings.scale).min(writer.height);
+            let pixels = if (width, height) == (source.width, source.height) {
+                source.pixels.clone()
+            } else {
+                let size = (source.width as usize, source.height as usize);
+                (self.resample)(&source.pixels, size, (width as usize, height as usize))
+                    .ok_or_else(|| std::io::Error::other("resample frame fail."))?
+            };
+
+            let mut frame = palette_frame(&pixels, width, height, settings.colors);
+            lossy_frame(&mut frame, None, settings.lossy);
+            // dropped frames hand their time to the frame that stays
+            frame.delay = group.iter().map(|source| source.delay).fold(0, u16::saturating_add);
+            writer.encoder.write_frame(&frame)?;
+        }
+
+        Ok(writer.encoder.into_inner()?)
+    }
+
+    /// Walks the colors, lossy, scale and frame step ladders in turn until the output fits the limit,
+    /// falling back to the smallest output seen when nothing does.
+    pub fn fit(&self) -> Result<(Settings, Vec<u8>), EncodingError> {
+        let limits = [COLORS.len(), LOSSY.len(), SCALES.len(), STEPS.len()];
+        let mut levels = [0usize; 4];
+        let mut turn = 0;
+        let mut settings = Settings::default();
+        let mut best: Option<(Settings, Vec<u8>)> = None;
+
+        loop {
+            let output = self.encode(&settings)?;
+            if output.len() <= self.limit {
+                return Ok((settings, output));
+            }
+            let smaller = match &best {
+                Some((_, smallest)) => output.len() < smallest.len(),
+                None => true,
+            };
+            if smaller {
+                best = Some((settings, output));
+            }
+
+            let knob = (0..4)
+                .map(|offset| (turn + offset) % 4)
+                .find(|&knob| levels[knob] + 1 < limits[knob]);
+            match knob {
+                Some(knob) => {
+                    levels[knob] += 1;
+                    turn = knob + 1;
+                }
+                None => break,
+            }
+            settings = Settings {
+                colors: COLORS[levels[0]],
+                lossy: LOSSY[levels[1]],
+                scale: SCALES[levels[2]],
+                step: STEPS[levels[3]],
+            };
+        }
+
+        Ok(best.expect("no attempt was encoded"))
+    }
+}
+
+#[test]
+fn budget_fit() {
+    let (width, height) = (96u16, 96u16);
+    // nearest neighbour stands in for the skia scaler, which needs a native build
+    let nearest: Resample = |pixels, (width, height), (target_width, target_height)| {
+        let scaled = (0..target_width * target_height)
+            .map(|i| pixels[i / target_width * height / target_height * width + i % target_width * width / target_width])
+            .collect();
+        Some(scaled)
+    };
+    let mut budget = Budget::new(width, height, usize::MAX, Repeat::Infinite, nearest);
+    for index in 0..6u32 {
+        let pixels: Vec<RGBA> = (0..width as u32 * height as u32)
+            .map(|i| {
+                let noise = (i.wrapping_mul(2654435761).wrapping_add(index * 40503) >> 24) as u8;
+                [(i % 96 * 2) as u8 ^ noise, (i / 96 * 2) as u8, noise, 0xFF]
+            })
+            .collect();
+        budget.push(&pixels, width, height, 10);
+    }
+
+    let (settings, output) = budget.fit().unwrap();
+    assert_eq!(settings, Settings::default());
+
+    budget.limit = output.len() / 2;
+    let (settings, smaller) = budget.fit().unwrap();
+    assert!(smaller.len() <= budget.limit);
+    assert_ne!(settings, Settings::default());
+
+    let mut decoder = DecodeOptions::new().read_info(smaller.as_slice()).unwrap();
+    assert_eq!(decoder.width(), scaled(width, settings.scale));
+    let mut delay = 0;
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert!(frame.palette.as_ref().unwrap().len() <= settings.colors * 3);
+        delay += frame.delay;
+    }
+    assert_eq!(delay, 60);
+}
@@ -0,0 +1,61 @@
+use gif::*;
+
+/// Trims the frame to the part inside `rect` (left, top, width, height on the logical screen),
+/// returning `false` and leaving the frame alone when nothing would remain.
+pub fn crop(frame: &mut Frame, rect: [u16; 4]) -> bool {
+    let [left, top, width, height] = rect.map(|value| value as usize);
+    let x0 = left.max(frame.left as usize);
+    let y0 = top.max(frame.top as usize);
+    let x1 = (left + width).min(frame.left as usize + frame.width as usize);
+    let y1 = (top + height).min(frame.top as usize + frame.height as usize);
+    if x0 >= x1 || y0 >= y1 {
+        return false;
+    }
+
+    let stride = frame.width as usize;
+    let (dx, dy) = (x0 - frame.left as usize, y0 - frame.top as usize);
+    let buffer: Vec<u8> = frame.buffer.chunks(stride)
+        .skip(dy)
+        .take(y1 - y0)
+        .flat_map(|row| &row[dx..dx + x1 - x0])
+        .copied()
+        .collect();
+
+    frame.buffer = buffer.into();
+    frame.left = x0 as _;
+    frame.top = y0 as _;
+    frame.width = (x1 - x0) as _;
+    frame.height = (y1 - y0) as _;
+    true
+}
+
+/// Moves the frame on the logical screen, returning `false` when it would leave the `u16` range.
+pub fn offset(frame: &mut Frame, dx: i32, dy: i32) -> bool {
+    let left = frame.left as i32 + dx;
+    let top = frame.top as i32 + dy;
+    if !(0..=u16::MAX as i32).contains(&left) || !(0..=u16::MAX as i32).contains(&top) {
+        return false;
+    }
+
+    frame.left = left as _;
+    frame.top = top as _;
+    true
+}
+
+#[test]
+fn crop_offset() {
+    let pixels: Vec<u8> = (0..6 * 4).collect();
+    let mut frame = Frame::from_indexed_pixels(6, 4, &pixels, None);
+    frame.left = 10;
+    frame.top = 20;
+
+    assert!(!crop(&mut frame, [0, 0, 10, 100]));
+    assert!(crop(&mut frame, [12, 19, 100, 3]));
+    assert_eq!([frame.left, frame.top, frame.width, frame.height], [12, 20, 4, 2]);
+    assert_eq!(frame.buffer.as_ref(), [2, 3, 4, 5, 8, 9, 10, 11]);
+
+    assert!(offset(&mut frame, -12, 5));
+    assert_eq!([frame.left, frame.top], [0, 25]);
+    assert!(!offset(&mut frame, -1, 0));
+    assert_eq!([frame.left, frame.top], [0, 25]);
+}
@@ -0,0 +1,84 @@
+use gif::*;
+
+/// Row numbers in the order the four interlace passes store them:
+/// every 8th row from 0, every 8th from 4, every 4th from 2, then every 2nd from 1.
+pub fn rows(height: usize) -> impl Iterator<Item = usize> {
+    [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .into_iter()
+        .flat_map(move |(start, step)| (start..height).step_by(step))
+}
+
+/// Reorders rows from display order into interlace order.
+pub fn interlace(buffer: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut interlaced = Vec::with_capacity(buffer.len());
+    for row in rows(height) {
+        interlaced.extend_from_slice(&buffer[row * width..][..width]);
+    }
+
+    interlaced
+}
+
+/// Reorders rows from interlace order back into display order.
+pub fn deinterlace(buffer: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut display = vec![0; width * height];
+    for (source, row) in rows(height).enumerate() {
+        display[row * width..][..width].copy_from_slice(&buffer[source * width..][..width]);
+    }
+
+    display
+}
+
+/// The frame as it goes on the wire, rows reordered and the interlace flag set.
+pub fn interlaced_frame<'a>(frame: &Frame<'a>) -> Frame<'a> {
+    let mut interlaced = frame.clone();
+    interlaced.buffer = interlace(&frame.buffer, frame.width as _, frame.height as _).into();
+    interlaced.interlaced = true;
+
+    interlaced
+}
+
+#[test]
+fn interlace_rows() {
+    let order: Vec<usize> = rows(11).collect();
+    assert_eq!(order, [0, 8, 4, 2, 6, 10, 1, 3, 5, 7, 9]);
+
+    for height in 0..20 {
+        let mut order: Vec<usize> = rows(height).collect();
+        order.sort_unstable();
+        assert_eq!(order, (0..height).collect::<Vec<_>>());
+    }
+
+    let buffer: Vec<u8> = (0..7 * 13).map(|i| i as u8).collect();
+    assert_eq!(deinterlace(&interlace(&buffer, 7, 13), 7, 13), buffer);
+}
+
+#[test]
+fn interlace_round_trip() {
+    let (width, height) = (19u16, 37u16);
+    let palette: Vec<u8> = (0..=255u8).flat_map(|i| [i, i, i]).collect();
+    let pixels: Vec<u8> = (0..width as u32 * height as u32)
+        .map(|i| (i / width as u32 * 7 + i % width as u32) as u8)
+        .collect();
+    let frame = Frame::from_palette_pixels(width, height, &pixels, &palette, None);
+
+    let mut output = Vec::new();
+    let mut encoder = Encoder::new(&mut output, width, height, &[]).unwrap();
+    encoder.write_frame(&interlaced_frame(&frame)).unwrap();
+    drop(encoder);
+
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(output.as_slice()).unwrap();
+    assert!(decoder.next_frame_info().unwrap().unwrap().interlaced);
+    let mut raw = vec![0; decoder.buffer_size()];
+    assert!(decoder.fill_buffer(&mut raw).unwrap());
+    assert_ne!(raw, pixels);
+    assert_eq!(deinterlace(&raw, width as _, height as _), pixels);
+
+    // the gif crate deinterlaces on its own as well
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::Indexed);
+    let mut decoder = options.read_info(output.as_slice()).unwrap();
+    let decoded = decoder.read_next_frame().unwrap().unwrap();
+    assert_eq!(decoded.buffer.as_ref(), pixels.as_slice());
+}
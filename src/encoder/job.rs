@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io::Seek;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use jni::{JavaVM, JNIEnv};
+use jni::objects::*;
+use jni::errors::Result;
+use super::pipeline::*;
+use super::writer::Writer;
+
+/// A Java object with `onProgress(int, long)`, `onComplete(int, long)` and `onError(String)`,
+/// called from native threads which attach to the JVM only for the duration of a call.
+pub struct Listener {
+    vm: JavaVM,
+    listener: GlobalRef,
+}
+
+impl Listener {
+    pub fn new(env: JNIEnv, listener: JObject) -> Result<Self> {
+        Ok(Listener {
+            vm: env.get_java_vm()?,
+            listener: env.new_global_ref(listener)?,
+        })
+    }
+
+    fn call(&self, name: &str, sig: &str, args: impl for<'a> FnOnce(&JNIEnv<'a>) -> Result<Vec<JValue<'a>>>) {
+        // there is no caller left to report a failed callback to, so it is dropped
+        let _ = self.vm.attach_current_thread().and_then(|env| {
+            let args = args(&env)?;
+            let call = env.call_method(self.listener.as_obj(), name, sig, &args);
+            // a throwing listener must not leave a pending exception on a native thread
+            if env.exception_check()? {
+                env.exception_clear()?;
+            }
+            call.map(|_| ())
+        });
+    }
+
+    pub fn progress(&self, frames: usize, bytes: u64) {
+        self.call("onProgress", "(IJ)V", |_| Ok(vec![JValue::Int(frames as _), JValue::Long(bytes as _)]));
+    }
+
+    pub fn complete(&self, frames: usize, bytes: u64) {
+        self.call("onComplete", "(IJ)V", |_| Ok(vec![JValue::Int(frames as _), JValue::Long(bytes as _)]));
+    }
+
+    pub fn error(&self, message: &str) {
+        self.call("onError", "(Ljava/lang/String;)V", |env| {
+            let message = env.new_string(message)?;
+            Ok(vec![JValue::Object(message.into())])
+        });
+    }
+}
+
+fn written(file: &mut File) -> u64 {
+    file.stream_position().unwrap_or(0)
+}
+
+/// Synchronizes itself so that `cancel` never waits behind a write blocked on a full pipeline.
+pub struct EncodeJob {
+    pipeline: Mutex<Option<Pipeline<File>>>,
+    canceller: Canceller,
+    listener: Arc<Listener>,
+}
+
+impl EncodeJob {
+    pub fn new(writer: Writer<File>, workers: usize, capacity: usize, listener: Listener) -> Self {
+        let listener = Arc::new(listener);
+        let progress: Progress<File> = {
+            let listener = Arc::clone(&listener);
+            Box::new(move |writer, frames| listener.progress(frames, written(writer.encoder.get_mut())))
+        };
+        let pipeline = Pipeline::with_progress(writer, workers, capacity, Some(progress));
+
+        EncodeJob {
+            canceller: pipeline.canceller(),
+            pipeline: Mutex::new(Some(pipeline)),
+            listener,
+        }
+    }
+
+    /// Writes go through this lock, it holds `None` once the job is finished.
+    pub fn pipeline(&self) -> &Mutex<Option<Pipeline<File>>> {
+        &self.pipeline
+    }
+
+    pub fn cancel(&self) {
+        self.canceller.cancel()
+    }
+
+    /// Returns at once, the trailer is written and the listener told on a separate thread.
+    pub fn finish(&self) {
+        let pipeline = self.pipeline.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        let pipeline = match pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let listener = Arc::clone(&self.listener);
+
+        thread::spawn(move || {
+            let frames = pipeline.submitted();
+            let result = pipeline.finish()
+                .map_err(|error| error.to_string())
+                .and_then(|writer| writer.encoder.into_inner().map_err(|error| error.to_string()));
+
+            match result {
+                Ok(mut file) => listener.complete(frames, written(&mut file)),
+                Err(message) => listener.error(&message),
+            }
+        });
+    }
+}
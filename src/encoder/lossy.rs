@@ -0,0 +1,131 @@
+use gif::*;
+
+const MAX_ENTRIES: usize = 1 << 12;
+
+fn distance(palette: &[u8], a: u8, b: u8) -> u32 {
+    let a = a as usize * 3;
+    let b = b as usize * 3;
+    if a + 3 > palette.len() || b + 3 > palette.len() {
+        return u32::MAX;
+    }
+
+    (0..3)
+        .map(|i| palette[a + i] as i32 - palette[b + i] as i32)
+        .map(|d| (d * d) as u32)
+        .sum()
+}
+
+fn min_code_size(indices: &[u8]) -> u8 {
+    let max = indices.iter().copied().max().unwrap_or(0) as u32;
+    // same rule as the gif encoder, the spec wants at least 2 bits
+    (u32::BITS - max.leading_zeros()).max(2) as u8
+}
+
+/// Rewrites indices so that runs which are close enough to an existing LZW string reuse it.
+///
+/// The dictionary is replayed exactly like the LZW encoder builds it: wherever the next pixel has no
+/// exact continuation, the nearest continuation whose palette color lies within `threshold` (RGB
+/// euclidean distance) is taken instead. Encoding the rewritten buffer with the ordinary LZW coder then
+/// yields the same codes a lossy coder would emit, so frames stay plain `gif::Frame`s.
+pub fn lossy_indices(indices: &mut [u8], palette: &[u8], transparent: Option<u8>, threshold: u32) {
+    if threshold == 0 || indices.is_empty() {
+        return;
+    }
+    let limit = threshold.saturating_mul(threshold);
+    let clear = 1usize << min_code_size(indices);
+
+    let mut next = vec![0u16; MAX_ENTRIES * 256];
+    let mut children: Vec<Vec<u8>> = vec![Vec::new(); MAX_ENTRIES];
+    let mut count = clear + 2;
+    let mut current = indices[0] as usize;
+
+    for index in indices[1..].iter_mut() {
+        let original = *index;
+        let exact = next[current * 256 + original as usize];
+        if exact != 0 {
+            current = exact as usize;
+            continue;
+        }
+
+        // transparency must survive untouched in both directions
+        let near = if Some(original) == transparent {
+            None
+        } else {
+            children[current].iter()
+                .copied()
+                .filter(|&symbol| Some(symbol) != transparent)
+                .map(|symbol| (distance(palette, symbol, original), symbol))
+                .filter(|&(distance, _)| distance <= limit)
+                .min()
+        };
+
+        match near {
+            Some((_, symbol)) => {
+                *index = symbol;
+                current = next[current * 256 + symbol as usize] as usize;
+            }
+            None => {
+                next[current * 256 + original as usize] = count as u16;
+                children[current].push(original);
+                count += 1;
+                if count > MAX_ENTRIES {
+                    for (code, symbols) in children.iter_mut().enumerate() {
+                        for symbol in symbols.drain(..) {
+                            next[code * 256 + symbol as usize] = 0;
+                        }
+                    }
+                    count = clear + 2;
+                }
+                current = original as usize;
+            }
+        }
+    }
+}
+
+pub fn lossy_frame(frame: &mut Frame, global_palette: Option<&[u8]>, threshold: u32) {
+    let palette = match frame.palette.clone().or_else(|| global_palette.map(<[u8]>::to_vec)) {
+        Some(palette) => palette,
+        None => return,
+    };
+
+    lossy_indices(frame.buffer.to_mut(), &palette, frame.transparent, threshold);
+}
+
+#[test]
+fn lossy_round_trip() {
+    let (width, height) = (64u16, 64u16);
+    // a ramp of near colors with noise, the kind of input where lossless LZW struggles
+    let palette: Vec<u8> = (0..64u8).flat_map(|i| [i * 4, i * 4, 128]).collect();
+    let original: Vec<u8> = (0..width as u32 * height as u32)
+        .map(|i| {
+            let noise = (i.wrapping_mul(2654435761) >> 29) as u8;
+            if i % 97 == 0 { 63 } else { ((i % 64) as u8 / 2 + noise).min(62) }
+        })
+        .collect();
+
+    let encode = |threshold: u32| {
+        let mut frame = Frame::from_palette_pixels(width, height, &original, &palette, Some(63));
+        lossy_frame(&mut frame, None, threshold);
+
+        let mut output = Vec::new();
+        let mut encoder = Encoder::new(&mut output, width, height, &[]).unwrap();
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        output
+    };
+
+    let lossless = encode(0);
+    let lossy = encode(24);
+    assert!(lossy.len() < lossless.len() * 9 / 10, "{} >= {}", lossy.len(), lossless.len());
+
+    let mut decoder = DecodeOptions::new().read_info(lossy.as_slice()).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    for (&decoded, &expected) in frame.buffer.iter().zip(&original) {
+        assert!(distance(&palette, decoded, expected) <= 24 * 24);
+        assert_eq!(decoded == 63, expected == 63);
+    }
+
+    let mut decoder = DecodeOptions::new().read_info(lossless.as_slice()).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert_eq!(frame.buffer.as_ref(), original.as_slice());
+}
@@ -1,2 +1,10 @@
+pub mod budget;
 pub mod ditherer;
-pub mod quantizer;
\ No newline at end of file
+pub mod frame;
+pub mod interlace;
+pub mod job;
+pub mod lossy;
+pub mod palette;
+pub mod pipeline;
+pub mod quantizer;
+pub mod writer;
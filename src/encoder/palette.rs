@@ -0,0 +1,97 @@
+use std::collections::*;
+use super::quantizer::distinct_elements;
+
+type RGBA = [u8; 4];
+
+type RGB = [u8; 3];
+
+fn distance(original: &RGBA, replacement: &RGB) -> u32 {
+    let r = original[0] as i32 - replacement[0] as i32;
+    let g = original[1] as i32 - replacement[1] as i32;
+    let b = original[2] as i32 - replacement[2] as i32;
+
+    (r * r + g * g + b * b) as u32
+}
+
+pub fn nearest_index(palette: &[RGB], color: &RGBA) -> u8 {
+    let (index, _) = palette.iter()
+        .enumerate()
+        .min_by_key(|(_, other)| distance(color, other))
+        .expect("palette is empty");
+
+    index as u8
+}
+
+pub fn remap(colors: &[RGBA], palette: &[RGB]) -> Vec<u8> {
+    let mut cache: HashMap<RGB, u8> = HashMap::new();
+    let mut indexed = Vec::with_capacity(colors.len());
+
+    for color in colors {
+        let rgb = [color[0], color[1], color[2]];
+        let index = *cache.entry(rgb)
+            .or_insert_with(|| nearest_index(palette, color));
+        indexed.push(index);
+    }
+
+    indexed
+}
+
+pub fn histogram(colors: &[RGBA], top: usize) -> (usize, Vec<(RGB, usize)>) {
+    let elements = distinct_elements(colors);
+    let mut entries: Vec<(RGB, usize)> = elements.into_iter().collect();
+    let unique = entries.len();
+
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(top);
+
+    (unique, entries)
+}
+
+pub fn quantization_error(colors: &[RGBA], palette: &[RGB]) -> (f64, f64, f64) {
+    let mut cache: HashMap<RGB, u32> = HashMap::new();
+    let mut sum = 0u64;
+    let mut max = 0u32;
+
+    for color in colors {
+        let rgb = [color[0], color[1], color[2]];
+        let error = *cache.entry(rgb)
+            .or_insert_with(|| distance(color, &palette[nearest_index(palette, color) as usize]));
+        sum += error as u64;
+        max = max.max(error);
+    }
+
+    let mse = if colors.is_empty() { 0.0 } else { sum as f64 / (colors.len() * 3) as f64 };
+    let psnr = if mse > 0.0 { 10.0 * (255.0 * 255.0 / mse).log10() } else { f64::INFINITY };
+
+    (mse, (max as f64).sqrt(), psnr)
+}
+
+#[test]
+fn remap_nearest() {
+    let palette = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+    let colors = [[10, 10, 10, 255], [250, 240, 245, 255], [200, 30, 20, 255], [0, 0, 0, 0]];
+
+    assert_eq!(remap(&colors, &palette), vec![0, 1, 2, 0]);
+}
+
+#[test]
+fn histogram_top() {
+    let colors = [[1, 2, 3, 255], [4, 5, 6, 255], [1, 2, 3, 255], [7, 8, 9, 255], [1, 2, 3, 0]];
+    let (unique, top) = histogram(&colors, 2);
+
+    assert_eq!(unique, 3);
+    assert_eq!(top, vec![([1, 2, 3], 3), ([4, 5, 6], 1)]);
+}
+
+#[test]
+fn quantization_error_exact() {
+    let colors = [[0, 0, 0, 255], [255, 255, 255, 255]];
+    let (mse, max, psnr) = quantization_error(&colors, &[[0, 0, 0], [255, 255, 255]]);
+
+    assert_eq!((mse, max), (0.0, 0.0));
+    assert!(psnr.is_infinite());
+
+    let (mse, max, _) = quantization_error(&colors, &[[0, 0, 0], [255, 255, 252]]);
+    assert_eq!(mse, 9.0 / 6.0);
+    assert_eq!(max, 3.0);
+}
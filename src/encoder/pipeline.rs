@@ -0,0 +1,352 @@
+use std::collections::*;
+use std::io::{self, Write};
+use std::sync::*;
+use std::sync::atomic::*;
+use std::thread::{self, JoinHandle};
+use gif::*;
+use super::interlace::interlace;
+use super::lossy::lossy_frame;
+use super::quantizer::thread_count;
+use super::writer::Writer;
+use crate::source::resize::*;
+
+pub struct Job {
+    pub pixels: Vec<u8>,
+    pub width: u16,
+    pub height: u16,
+    pub left: u16,
+    pub top: u16,
+    pub delay: u16,
+    pub dispose: DisposalMethod,
+    pub speed: i32,
+    pub lossy: u32,
+}
+
+impl Job {
+    fn encode(mut self, interlaced: bool) -> Frame<'static> {
+        let mut frame = Frame::from_rgba_speed(self.width, self.height, &mut self.pixels, self.speed);
+        if interlaced {
+            // pre-encoded frames skip the writer, so rows are reordered before compression
+            frame.buffer = interlace(&frame.buffer, self.width as _, self.height as _).into();
+            frame.interlaced = true;
+        }
+        // the lossy pass has to see the rows in the order they are compressed
+        lossy_frame(&mut frame, None, self.lossy);
+        frame.left = self.left;
+        frame.top = self.top;
+        frame.delay = self.delay;
+        frame.dispose = self.dispose;
+        frame.make_lzw_pre_encoded();
+        frame
+    }
+}
+
+/// Frames that were submitted but not yet written, whether queued, compressing or waiting for
+/// their turn in the reorder buffer.
+struct Window {
+    pending: Mutex<usize>,
+    written: Condvar,
+    cancelled: AtomicBool,
+}
+
+impl Window {
+    fn acquire(&self, capacity: usize) {
+        let mut pending = self.pending.lock().unwrap();
+        while *pending >= capacity && !self.cancelled() {
+            pending = self.written.wait(pending).unwrap();
+        }
+        *pending += 1;
+    }
+
+    fn release(&self) {
+        *self.pending.lock().unwrap() -= 1;
+        self.written.notify_one();
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Cancels a pipeline from any thread, without needing access to the pipeline itself.
+#[derive(Clone)]
+pub struct Canceller(Arc<Window>);
+
+impl Canceller {
+    /// Stops compressing and writing, and wakes producers waiting for room.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        let _pending = self.0.pending.lock().unwrap();
+        self.0.written.notify_all();
+    }
+}
+
+/// Called on the writer thread after every written frame with the number of frames written so far.
+pub type Progress<W> = Box<dyn FnMut(&mut Writer<W>, usize) + Send>;
+
+pub struct Pipeline<W: Write + Send + 'static> {
+    pub width: u16,
+    pub height: u16,
+    pub resize: Resize,
+    pub sampling: Sampling,
+    jobs: Option<mpsc::Sender<(usize, Job)>>,
+    workers: Vec<JoinHandle<()>>,
+    output: Option<JoinHandle<Result<Writer<W>, EncodingError>>>,
+    window: Arc<Window>,
+    capacity: usize,
+    count: usize,
+}
+
+impl<W: Write + Send + 'static> Pipeline<W> {
+    /// Moves the writer onto its own thread and starts `workers` compressing threads,
+    /// `0` meaning one per core. At most `capacity` frames are held in memory at a time,
+    /// `0` meaning twice the worker count.
+    pub fn new(writer: Writer<W>, workers: usize, capacity: usize) -> Self {
+        Self::with_progress(writer, workers, capacity, None)
+    }
+
+    pub fn with_progress(writer: Writer<W>, workers: usize, capacity: usize, progress: Option<Progress<W>>) -> Self {
+        let workers = thread_count(workers);
+        let capacity = if capacity == 0 { workers * 2 } else { capacity };
+        let window = Arc::new(Window {
+            pending: Mutex::new(0),
+            written: Condvar::new(),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let (width, height, resize, sampling) = (writer.width, writer.height, writer.resize, writer.sampling);
+        let interlaced = writer.interlaced;
+        let (jobs, queue) = mpsc::channel::<(usize, Job)>();
+        let (frames, encoded) = mpsc::channel::<(usize, Option<Frame<'static>>)>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        let workers = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let frames = frames.clone();
+                let window = Arc::clone(&window);
+                thread::spawn(move || loop {
+                    let job = queue.lock().unwrap().recv();
+                    match job {
+                        Ok((index, job)) => {
+                            // cancelled jobs still report back so the window drains
+                            let frame = (!window.cancelled()).then(|| job.encode(interlaced));
+                            if frames.send((index, frame)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        drop(frames);
+
+        let output = {
+            let window = Arc::clone(&window);
+            thread::spawn(move || {
+                let mut writer = writer;
+                let mut progress = progress;
+                let mut result = Ok(());
+                let mut reorder = BTreeMap::new();
+                let mut next = 0;
+
+                for (index, frame) in encoded {
+                    reorder.insert(index, frame);
+                    while let Some(frame) = reorder.remove(&next) {
+                        // keep draining after a failure so producers never block on the window
+                        if let (Ok(_), Some(frame), false) = (&result, frame, window.cancelled()) {
+                            result = writer.encoder.write_lzw_pre_encoded_frame(&frame);
+                            if let (Ok(_), Some(progress)) = (&result, progress.as_mut()) {
+                                progress(&mut writer, next + 1);
+                            }
+                        }
+                        next += 1;
+                        window.release();
+                    }
+                }
+
+                if window.cancelled() {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "encoding cancelled").into());
+                }
+                result.map(|_| writer)
+            })
+        };
+
+        Pipeline {
+            width,
+            height,
+            resize,
+            sampling,
+            jobs: Some(jobs),
+            workers,
+            output: Some(output),
+            window,
+            capacity,
+            count: 0,
+        }
+    }
+
+    pub fn placement(&self, width: i32, height: i32) -> Option<Placement> {
+        placement(self.resize, (width.max(0) as _, height.max(0) as _), (self.width, self.height))
+    }
+
+    /// Queues a frame, blocking while the pipeline already holds `capacity` frames.
+    /// Frames submitted after a cancel are dropped, an error means every worker has died.
+    pub fn submit(&mut self, job: Job) -> Result<(), EncodingError> {
+        if self.window.cancelled() {
+            return Ok(());
+        }
+        self.window.acquire(self.capacity);
+        let sent = match &self.jobs {
+            Some(jobs) => jobs.send((self.count, job)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.window.release();
+            return Err(failure("pipeline workers are gone"));
+        }
+        self.count += 1;
+
+        Ok(())
+    }
+
+    pub fn submitted(&self) -> usize {
+        self.count
+    }
+
+    pub fn canceller(&self) -> Canceller {
+        Canceller(Arc::clone(&self.window))
+    }
+
+    pub fn cancel(&self) {
+        self.canceller().cancel()
+    }
+
+    /// Cancels and waits for every thread to exit, the writer is dropped without a trailer.
+    pub fn close(self) {
+        self.cancel();
+        let _ = self.finish();
+    }
+
+    /// Waits for every submitted frame to be written and hands the writer back.
+    pub fn finish(mut self) -> Result<Writer<W>, EncodingError> {
+        self.jobs.take();
+        // every worker is joined before the writer, a dead one still lets the others drain
+        let joined: Vec<_> = self.workers.drain(..)
+            .map(|worker| worker.join())
+            .collect();
+        let panicked = joined.iter().any(|result| result.is_err());
+
+        let writer = match self.output.take() {
+            Some(output) => output.join().map_err(|_| failure("pipeline writer panicked"))?,
+            None => return Err(failure("pipeline already finished")),
+        };
+        if panicked {
+            return Err(failure("pipeline worker panicked"));
+        }
+        writer
+    }
+}
+
+fn failure(message: &str) -> EncodingError {
+    io::Error::other(message).into()
+}
+
+#[test]
+fn pipeline_in_order() {
+    let (width, height) = (32u16, 24u16);
+    let jobs = || {
+        (0..9u32).map(move |index| {
+            let pixels: Vec<u8> = (0..width as u32 * height as u32)
+                .flat_map(|i| [(i * 7 + index * 31) as u8, (i / 32 * 9) as u8, (index * 28) as u8, 0xFF])
+                .collect();
+            Job {
+                pixels,
+                width,
+                height,
+                left: 0,
+                top: 0,
+                delay: index as u16,
+                dispose: DisposalMethod::Keep,
+                speed: 10,
+                lossy: 0,
+            }
+        })
+    };
+
+    let mut sequential = Writer::new(Vec::new(), width, height, &[]).unwrap();
+    for job in jobs() {
+        let mut frame = Frame::from_rgba_speed(job.width, job.height, &mut job.pixels.clone(), job.speed);
+        frame.delay = job.delay;
+        frame.dispose = job.dispose;
+        sequential.encoder.write_frame(&frame).unwrap();
+    }
+    let expected = sequential.encoder.into_inner().unwrap();
+
+    let writer = Writer::new(Vec::new(), width, height, &[]).unwrap();
+    let mut pipeline = Pipeline::new(writer, 3, 2);
+    for job in jobs() {
+        pipeline.submit(job).unwrap();
+    }
+    let output = pipeline.finish().unwrap().encoder.into_inner().unwrap();
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn pipeline_cancel() {
+    let (width, height) = (16u16, 16u16);
+    let writer = Writer::new(Vec::new(), width, height, &[]).unwrap();
+    let written = Arc::new(AtomicUsize::new(0));
+    let progress: Progress<Vec<u8>> = {
+        let written = Arc::clone(&written);
+        Box::new(move |_, count| written.store(count, Ordering::Relaxed))
+    };
+    let mut pipeline = Pipeline::with_progress(writer, 2, 2, Some(progress));
+
+    let job = || Job {
+        pixels: vec![0x80; width as usize * height as usize * 4],
+        width,
+        height,
+        left: 0,
+        top: 0,
+        delay: 0,
+        dispose: DisposalMethod::Keep,
+        speed: 10,
+        lossy: 0,
+    };
+    for _ in 0..3 {
+        pipeline.submit(job()).unwrap();
+    }
+    pipeline.cancel();
+    pipeline.submit(job()).unwrap();
+
+    assert_eq!(pipeline.submitted(), 3);
+    assert!(pipeline.finish().is_err());
+    assert!(written.load(Ordering::Relaxed) <= 3);
+}
+
+#[test]
+fn pipeline_close() {
+    let (width, height) = (16u16, 16u16);
+    let writer = Writer::new(Vec::new(), width, height, &[]).unwrap();
+    let mut pipeline = Pipeline::new(writer, 2, 1);
+
+    for _ in 0..4 {
+        pipeline.submit(Job {
+            pixels: vec![0x40; width as usize * height as usize * 4],
+            width,
+            height,
+            left: 0,
+            top: 0,
+            delay: 0,
+            dispose: DisposalMethod::Keep,
+            speed: 10,
+            lossy: 0,
+        }).unwrap();
+    }
+
+    // returns only once the workers and the writer thread have exited
+    pipeline.close();
+}
@@ -1,101 +1,218 @@
-use std::cell::*;
 use std::cmp::*;
 use std::collections::*;
-use std::ops::DerefMut;
-use std::rc::Rc;
+use std::thread;
 
 type RGBA = [u8; 4];
 
 type RGB = [u8; 3];
 
-// region OctTree Quantizer
+#[cfg(test)]
+mod legacy;
 
-type Link = Rc<RefCell<Node>>;
+// region Palette Sort
 
-#[derive(Debug)]
-enum Node {
-    Leaf {
-        red_sum: u32,
-        green_sum: u32,
-        blue_sum: u32,
-        pixel_count: u32,
-    },
-    Branch {
-        children: [Option<Link>; 8],
-    },
-}
-
-impl Node {
-    fn value(&self) -> (u32, u32, u32, u32) {
-        match self {
-            Node::Leaf {
-                red_sum,
-                green_sum,
-                blue_sum,
-                pixel_count,
-            } => (*red_sum, *green_sum, *blue_sum, *pixel_count),
-            Node::Branch { .. } => (0, 0, 0, 0),
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaletteSort {
+    None = 0,
+    RGB = 1,
+    Luminance = 2,
+    Hue = 3,
+    Frequency = 4,
+}
+
+impl PaletteSort {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(PaletteSort::None),
+            1 => Some(PaletteSort::RGB),
+            2 => Some(PaletteSort::Luminance),
+            3 => Some(PaletteSort::Hue),
+            4 => Some(PaletteSort::Frequency),
+            _ => None,
         }
     }
+}
 
-    fn palette_sort(&self, colors: &mut BTreeSet<RGB>) {
-        match self {
-            Node::Leaf {
-                red_sum,
-                green_sum,
-                blue_sum,
-                pixel_count,
-            } => {
-                colors.insert([
-                    ((red_sum / pixel_count) & 0xFF) as u8,
-                    ((green_sum / pixel_count) & 0xFF) as u8,
-                    ((blue_sum / pixel_count) & 0xFF) as u8
-                ]);
-            }
-            Node::Branch { children } => {
-                for child in children.iter() {
-                    if let Some(link) = child {
-                        link.try_borrow_mut()
-                            .expect("palette get node")
-                            .palette_sort(colors);
-                    }
-                }
-            }
+fn luminance(color: &RGB) -> u32 {
+    299 * color[0] as u32 + 587 * color[1] as u32 + 114 * color[2] as u32
+}
+
+fn hue(color: &RGB) -> f32 {
+    let r = color[0] as f32;
+    let g = color[1] as f32;
+    let b = color[2] as f32;
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    if delta == 0.0 {
+        return -1.0;
+    }
+
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+
+    hue * 60.0
+}
+
+pub fn sort_entries(entries: Vec<(RGB, usize)>, sort: PaletteSort) -> Vec<(RGB, usize)> {
+    let mut merged: HashMap<RGB, usize> = HashMap::with_capacity(entries.len());
+    for (color, count) in entries {
+        *merged.entry(color).or_insert(0) += count;
+    }
+    let mut entries: Vec<(RGB, usize)> = merged.into_iter().collect();
+
+    match sort {
+        PaletteSort::None => {}
+        PaletteSort::RGB => entries.sort_by_key(|(color, _)| *color),
+        PaletteSort::Luminance => entries.sort_by_key(|(color, _)| (luminance(color), *color)),
+        PaletteSort::Hue => entries.sort_by(|(a, _), (b, _)| {
+            hue(a).total_cmp(&hue(b)).then(luminance(a).cmp(&luminance(b)))
+        }),
+        PaletteSort::Frequency => entries.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b))),
+    }
+
+    entries
+}
+
+pub fn sort_palette(entries: Vec<(RGB, usize)>, sort: PaletteSort) -> Vec<RGB> {
+    sort_entries(entries, sort).into_iter().map(|(color, _)| color).collect()
+}
+
+// end region
+
+// region Histogram
+
+const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+pub fn distinct_elements(colors: &[RGBA]) -> HashMap<RGB, usize> {
+    let mut map = HashMap::with_capacity(min(colors.len(), 1 << 16));
+
+    for color in colors {
+        let rgb = [color[0], color[1], color[2]];
+        let count = map.entry(rgb).or_insert(0);
+        *count += 1;
+    }
+
+    map
+}
+
+pub fn thread_count(threads: usize) -> usize {
+    if threads == 0 {
+        thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+    } else {
+        threads
+    }
+}
+
+pub fn parallel_elements(colors: &[RGBA], threads: usize) -> HashMap<RGB, usize> {
+    let threads = thread_count(threads);
+    if threads <= 1 || colors.len() < PARALLEL_THRESHOLD {
+        return distinct_elements(colors);
+    }
+
+    let chunk_size = colors.len().div_ceil(threads);
+    let parts: Vec<HashMap<RGB, usize>> = thread::scope(|scope| {
+        let handles: Vec<_> = colors.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || distinct_elements(chunk)))
+            .collect();
+
+        handles.into_iter()
+            .map(|handle| handle.join().expect("histogram thread panicked"))
+            .collect()
+    });
+
+    let mut parts = parts.into_iter();
+    let mut map = parts.next().unwrap_or_default();
+    for part in parts {
+        for (color, count) in part {
+            *map.entry(color).or_insert(0) += count;
         }
     }
 
-    fn palette(&self, colors: &mut HashSet<RGB>) {
-        match self {
-            Node::Leaf {
-                red_sum,
-                green_sum,
-                blue_sum,
-                pixel_count,
-            } => {
-                colors.insert([
-                    ((red_sum / pixel_count) & 0xFF) as u8,
-                    ((green_sum / pixel_count) & 0xFF) as u8,
-                    ((blue_sum / pixel_count) & 0xFF) as u8
-                ]);
-            }
-            Node::Branch { children } => {
-                for child in children.iter() {
-                    if let Some(link) = child {
-                        link.try_borrow_mut()
-                            .expect("palette get node")
-                            .palette(colors);
-                    }
-                }
-            }
+    map
+}
+
+#[derive(Clone, Debug, Default)]
+struct Bin {
+    count: u64,
+    sum: [u64; 3],
+    square: [u64; 3],
+}
+
+impl Bin {
+    fn new(color: &RGB, count: usize) -> Self {
+        let count = count as u64;
+        Bin {
+            count,
+            sum: color.map(|value| value as u64 * count),
+            square: color.map(|value| value as u64 * value as u64 * count),
         }
     }
+
+    fn add(&mut self, other: &Bin) {
+        self.count += other.count;
+        for component in 0..3 {
+            self.sum[component] += other.sum[component];
+            self.square[component] += other.square[component];
+        }
+    }
+
+    fn color(&self) -> RGB {
+        [
+            (self.sum[0] / self.count) as u8,
+            (self.sum[1] / self.count) as u8,
+            (self.sum[2] / self.count) as u8
+        ]
+    }
+
+    fn error(&self, component: usize) -> f64 {
+        let sum = self.sum[component] as f64;
+        self.square[component] as f64 - sum * sum / self.count as f64
+    }
+
+    fn total_error(&self) -> f64 {
+        (0..3).map(|component| self.error(component)).sum()
+    }
+}
+
+fn color_bins(elements: HashMap<RGB, usize>) -> Vec<(RGB, Bin)> {
+    elements.into_iter()
+        .map(|(color, count)| (color, Bin::new(&color, count)))
+        .collect()
+}
+
+// end region
+
+// region OctTree Quantizer
+
+const NONE: u32 = 0;
+
+const LEAF_BUDGET: usize = 256;
+
+#[derive(Debug, Default)]
+struct Node {
+    red_sum: u64,
+    green_sum: u64,
+    blue_sum: u64,
+    pixel_count: u64,
+    leaf: bool,
+    children: [u32; 8],
 }
 
 #[derive(Debug)]
 struct OctTree {
     leaf_count: usize,
-    root: Option<Link>,
-    node_list: [Vec<Link>; 8],
+    nodes: Vec<Node>,
+    free: Vec<u32>,
+    node_list: [Vec<u32>; 8],
+    sorted: [bool; 8],
 }
 
 const MASK: [u8; 8] = [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01];
@@ -104,142 +221,216 @@ impl OctTree {
     fn new() -> Self {
         let mut tree = OctTree {
             leaf_count: 0,
-            root: None,
+            nodes: Vec::new(),
+            free: Vec::new(),
             node_list: MASK.map(|_| Vec::new()),
+            sorted: [true; 8],
         };
 
-        tree.root = Some(tree.create_node(0));
+        tree.create_node(0);
 
         tree
     }
 
-    fn create_node(&mut self, level: usize) -> Link {
+    fn create_node(&mut self, level: usize) -> u32 {
+        let node = Node { leaf: level == 8, ..Node::default() };
+        let index = if let Some(index) = self.free.pop() {
+            self.nodes[index as usize] = node;
+            index
+        } else {
+            self.nodes.push(node);
+            (self.nodes.len() - 1) as u32
+        };
+
         if level == 8 {
             self.leaf_count += 1;
+        } else {
+            self.node_list[level].push(index);
+        }
 
-            let node = Node::Leaf {
-                red_sum: 0,
-                green_sum: 0,
-                blue_sum: 0,
-                pixel_count: 0,
-            };
+        index
+    }
 
-            Rc::new(RefCell::new(node))
-        } else {
-            let node = Node::Branch {
-                children: MASK.map(|_| None as Option<Link>),
-            };
-            let link = Rc::new(RefCell::new(node));
-            self.node_list[level].push(link.clone());
+    fn add_color(&mut self, color: &RGB, sums: [u64; 4]) {
+        let mut index = 0;
+        let mut level = 0;
+
+        while !self.nodes[index as usize].leaf {
+            let shift = 7 - level;
+            let mask = MASK[level];
+            let n_index = ((color[0] & mask) as usize >> shift << 2)
+                | ((color[1] & mask) as usize >> shift << 1)
+                | ((color[2] & mask) as usize >> shift);
+
+            let mut child = self.nodes[index as usize].children[n_index];
+            if child == NONE {
+                child = self.create_node(level + 1);
+                self.nodes[index as usize].children[n_index] = child;
+            }
 
-            link
+            index = child;
+            level += 1;
         }
+
+        let node = &mut self.nodes[index as usize];
+        node.red_sum += sums[0];
+        node.green_sum += sums[1];
+        node.blue_sum += sums[2];
+        node.pixel_count += sums[3];
     }
 
-    fn add_color(&mut self, link: &mut Link, color: &RGBA, in_level: usize) {
-        let mut node: RefMut<Node> = link.try_borrow_mut().expect("add_color get node");
-
-        match node.deref_mut() {
-            Node::Leaf {
-                pixel_count,
-                red_sum,
-                green_sum,
-                blue_sum,
-                ..
-            } => {
-                *pixel_count += 1;
-                *red_sum += color[0] as u32;
-                *green_sum += color[1] as u32;
-                *blue_sum += color[2] as u32;
-            }
-            Node::Branch { children, .. } => {
-                let shift = 7 - in_level;
-                let mask = MASK[in_level];
-                let n_index = (((color[0] & mask) as usize >> shift << 2)
-                    | ((color[1] & mask) as usize >> shift << 1)
-                    | ((color[2] & mask) as usize >> shift)) as usize;
+    fn population(&self, index: u32) -> u64 {
+        self.nodes[index as usize].children.iter()
+            .filter(|child| **child != NONE)
+            .map(|child| self.nodes[*child as usize].pixel_count)
+            .sum()
+    }
 
-                let link = children[n_index]
-                    .get_or_insert_with(|| self.create_node(in_level + 1));
+    fn reduce_tree(&mut self) -> bool {
+        // once the root has been folded there is nothing left to merge
+        let level = match self.node_list.iter().rposition(|vec| !vec.is_empty()) {
+            Some(level) => level,
+            None => return false
+        };
 
-                self.add_color(link, color, in_level + 1)
+        if !self.sorted[level] {
+            let mut list = std::mem::take(&mut self.node_list[level]);
+            list.sort_by_cached_key(|index| Reverse(self.population(*index)));
+            self.node_list[level] = list;
+            self.sorted[level] = true;
+        }
+
+        let index = self.node_list[level]
+            .pop()
+            .expect("reduce_tree get index") as usize;
+
+        let children = std::mem::take(&mut self.nodes[index].children);
+        let mut merged = Node { leaf: true, ..Node::default() };
+        for child in children {
+            if child != NONE {
+                let node = &self.nodes[child as usize];
+                merged.red_sum += node.red_sum;
+                merged.green_sum += node.green_sum;
+                merged.blue_sum += node.blue_sum;
+                merged.pixel_count += node.pixel_count;
+
+                self.free.push(child);
+                self.leaf_count -= 1
             }
         }
+
+        self.nodes[index] = merged;
+
+        self.leaf_count += 1;
+
+        true
     }
 
-    fn reduce_tree(&mut self) {
-        let mut red_sum: u32 = 0;
-        let mut green_sum: u32 = 0;
-        let mut blue_sum: u32 = 0;
-        let mut pixel_count: u32 = 0;
+    fn reduce(&mut self, max_color_count: usize) {
+        self.sorted = [false; 8];
 
-        let link = self
-            .node_list
-            .iter_mut()
-            .rfind(|vec| !vec.is_empty())
-            .expect("reduce_tree get vec")
-            .pop()
-            .expect("reduce_tree get link");
-
-        let mut current = link.try_borrow_mut().expect("reduce_tree get current");
-
-        match current.deref_mut() {
-            Node::Leaf { .. } => {}
-            Node::Branch { children, .. } => {
-                for child in children.iter() {
-                    if let Some(link) = child {
-                        let (red, green, blue, pixel) = link.try_borrow()
-                            .expect("get link value")
-                            .value();
-                        red_sum += red;
-                        green_sum += green;
-                        blue_sum += blue;
-                        pixel_count += pixel;
-
-                        self.leaf_count -= 1
-                    }
+        while self.leaf_count > max_color_count && self.reduce_tree() {}
+    }
+
+    fn leaves(&self) -> Vec<&Node> {
+        let mut leaves = Vec::with_capacity(self.leaf_count);
+        let mut stack = vec![0u32];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if node.leaf {
+                if node.pixel_count > 0 {
+                    leaves.push(node);
                 }
+            } else {
+                stack.extend(node.children.iter().rev().filter(|child| **child != NONE));
             }
         }
 
-        let leaf = Node::Leaf {
-            red_sum,
-            green_sum,
-            blue_sum,
-            pixel_count,
-        };
+        leaves
+    }
+
+    fn insert(&mut self, colors: &[RGBA], budget: usize) {
+        for run in colors.chunk_by(|a, b| a[..3] == b[..3]) {
+            let color = [run[0][0], run[0][1], run[0][2]];
+            let count = run.len() as u64;
+            self.add_color(&color, [color[0] as u64 * count, color[1] as u64 * count, color[2] as u64 * count, count]);
 
-        *current = leaf;
+            if self.leaf_count > budget {
+                self.reduce(budget / 2)
+            }
+        }
+    }
 
-        self.leaf_count += 1
+    fn merge(&mut self, other: &OctTree, budget: usize) {
+        for node in other.leaves() {
+            let color = [
+                (node.red_sum / node.pixel_count) as u8,
+                (node.green_sum / node.pixel_count) as u8,
+                (node.blue_sum / node.pixel_count) as u8
+            ];
+            self.add_color(&color, [node.red_sum, node.green_sum, node.blue_sum, node.pixel_count]);
+
+            if self.leaf_count > budget {
+                self.reduce(budget / 2)
+            }
+        }
     }
 
-    fn color_palette(&self, sort: bool) -> Vec<RGB> {
-        let node: Ref<Node> = self.root.as_ref().unwrap().try_borrow().unwrap();
+    fn color_palette(&self, sort: PaletteSort) -> Vec<(RGB, usize)> {
+        let entries = self.leaves().into_iter()
+            .map(|node| ([
+                (node.red_sum / node.pixel_count) as u8,
+                (node.green_sum / node.pixel_count) as u8,
+                (node.blue_sum / node.pixel_count) as u8
+            ], node.pixel_count as usize))
+            .collect();
 
-        if sort {
-            let mut palette = BTreeSet::new();
-            node.palette_sort(&mut palette);
-            palette.into_iter().collect()
-        } else {
-            let mut palette = HashSet::new();
-            node.palette(&mut palette);
-            palette.into_iter().collect()
-        }
+        sort_entries(entries, sort)
     }
 }
 
-pub fn octtree_quantizer(colors: &[RGBA], max_color_count: usize, sort: bool) -> Vec<RGB> {
-    let mut tree = OctTree::new();
-    let mut root = tree.root.clone().unwrap();
+pub fn octtree_quantizer(colors: &[RGBA], max_color_count: usize, sort: PaletteSort, threads: usize) -> Vec<RGB> {
+    octtree_populations(colors, max_color_count, sort, threads)
+        .into_iter()
+        .map(|(color, _)| color)
+        .collect()
+}
 
-    for color in colors {
-        tree.add_color(&mut root, color, 0);
+pub fn octtree_populations(colors: &[RGBA], max_color_count: usize, sort: PaletteSort, threads: usize) -> Vec<(RGB, usize)> {
+    // the tree may grow past the palette size before it is reduced, so the least populated
+    // nodes are picked among many candidates instead of whichever pixel came last
+    let budget = max(max_color_count, LEAF_BUDGET) * 2;
+    let threads = thread_count(threads);
+
+    let mut tree = if threads <= 1 || colors.len() < PARALLEL_THRESHOLD {
+        let mut tree = OctTree::new();
+        tree.insert(colors, budget);
+        tree
+    } else {
+        let chunk_size = colors.len().div_ceil(threads);
+        let trees: Vec<OctTree> = thread::scope(|scope| {
+            let handles: Vec<_> = colors.chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || {
+                    let mut tree = OctTree::new();
+                    tree.insert(chunk, budget);
+                    tree
+                }))
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().expect("octtree thread panicked"))
+                .collect()
+        });
 
-        while tree.leaf_count > max_color_count {
-            tree.reduce_tree()
+        let mut trees = trees.into_iter();
+        let mut tree = trees.next().expect("octtree get tree");
+        for other in trees {
+            tree.merge(&other, budget);
         }
-    }
+        tree
+    };
+    tree.reduce(max_color_count);
 
     tree.color_palette(sort)
 }
@@ -251,213 +442,346 @@ pub fn octtree_quantizer(colors: &[RGBA], max_color_count: usize, sort: bool) ->
 
 #[derive(Debug)]
 struct Cluster {
-    raw: Vec<RGB>,
-    largest_spread: u8,
-    component_with_largest_spread: usize,
+    start: usize,
+    end: usize,
+    total: Bin,
+    min: RGB,
+    max: RGB,
 }
 
 impl Cluster {
-    fn new(raw: Vec<RGB>) -> Self {
-        let mut largest_spread: u8 = 0;
-        let mut component_with_largest_spread: usize = 1;
+    fn new(bins: &[(RGB, Bin)], start: usize, end: usize) -> Self {
+        let mut cluster = Cluster {
+            start,
+            end,
+            total: Bin::default(),
+            min: [0xFF; 3],
+            max: [0x00; 3],
+        };
 
-        for component in 0..3 {
-            let mut min_: u8 = 0xFF;
-            let mut max_: u8 = 0x00;
-            for color in &raw {
-                min_ = min(min_, color[component]);
-                max_ = max(max_, color[component]);
-            }
+        for (color, bin) in &bins[start..end] {
+            cluster.total.add(bin);
+            cluster.min = [0, 1, 2].map(|component| min(cluster.min[component], color[component]));
+            cluster.max = [0, 1, 2].map(|component| max(cluster.max[component], color[component]));
+        }
 
-            let spread = max_ - min_;
+        cluster
+    }
 
-            if spread > largest_spread {
-                largest_spread = spread;
-                component_with_largest_spread = component;
-            }
-        }
+    fn spread(&self, component: usize) -> u8 {
+        self.max[component] - self.min[component]
+    }
 
-        Cluster {
-            raw,
-            largest_spread,
-            component_with_largest_spread,
-        }
+    fn splittable(&self) -> bool {
+        self.end - self.start > 1
     }
 
-    fn form_rgba(colors: &[RGBA]) -> Self {
-        let mut raw = Vec::with_capacity(colors.len());
-        for rgba in colors {
-            raw.push([rgba[0], rgba[1], rgba[2]])
+    fn priority(&self, variance: bool) -> f64 {
+        if variance {
+            self.total.total_error()
+        } else {
+            (0..3).map(|component| self.spread(component)).max().unwrap_or(0) as f64
         }
-        Self::new(raw)
     }
 
-    fn form_rgb(colors: &[RGB]) -> Self {
-        Self::new(Vec::from(colors))
+    fn split(&self, bins: &mut [(RGB, Bin)], variance: bool, optimize: bool) -> (Cluster, Cluster) {
+        let component = if variance {
+            (0..3).max_by(|a, b| self.total.error(*a).total_cmp(&self.total.error(*b)))
+        } else {
+            (0..3).max_by_key(|component| self.spread(*component))
+        }.expect("mediancut get component");
+
+        let range = &mut bins[self.start..self.end];
+        range.sort_unstable_by_key(|(color, _)| (color[component], *color));
+
+        let point = if optimize {
+            Self::optimal_point(range, &self.total)
+        } else {
+            Self::median_point(range, self.total.count)
+        };
+
+        (
+            Cluster::new(bins, self.start, self.start + point),
+            Cluster::new(bins, self.start + point, self.end),
+        )
     }
 
-    fn split(&mut self) -> Vec<Cluster> {
-        self.raw.sort_by_key(|color| color[self.component_with_largest_spread]);
-        let median = self.raw.len() / 2;
+    fn median_point(range: &[(RGB, Bin)], total: u64) -> usize {
+        let mut count = 0u64;
+        for (index, (_, bin)) in range.iter().enumerate() {
+            count += bin.count;
+            if count * 2 >= total {
+                return (index + 1).clamp(1, range.len() - 1);
+            }
+        }
 
-        vec![
-            Cluster::form_rgb(&self.raw[..median]),
-            Cluster::form_rgb(&self.raw[median..]),
-        ]
+        range.len() / 2
     }
 
-    fn avg(&self) -> RGB {
-        let total = self.raw.len() as u64;
-        let sum = self.raw.iter().fold([0u64; 3], |acc, color| {
-            [
-                acc[0] + color[0] as u64,
-                acc[1] + color[1] as u64,
-                acc[2] + color[2] as u64
-            ]
-        });
+    fn optimal_point(range: &[(RGB, Bin)], total: &Bin) -> usize {
+        let mut left = Bin::default();
+        let mut best = (f64::INFINITY, range.len() / 2);
 
-        [
-            (sum[0] / total) as u8,
-            (sum[1] / total) as u8,
-            (sum[2] / total) as u8
-        ]
+        for (index, (_, bin)) in range[..range.len() - 1].iter().enumerate() {
+            left.add(bin);
+            let right = Bin {
+                count: total.count - left.count,
+                sum: [0, 1, 2].map(|component| total.sum[component] - left.sum[component]),
+                square: [0, 1, 2].map(|component| total.square[component] - left.square[component]),
+            };
+
+            let value = left.total_error() + right.total_error();
+            if value < best.0 {
+                best = (value, index + 1);
+            }
+        }
+
+        best.1
     }
 }
 
-pub fn mediancut_quantizer(colors: &[RGBA], max_color_count: usize, sort: bool) -> Vec<RGB> {
+pub fn mediancut_quantizer(
+    colors: &[RGBA], max_color_count: usize, sort: PaletteSort, threads: usize, variance: bool, optimize: bool,
+) -> Vec<RGB> {
+    mediancut_populations(colors, max_color_count, sort, threads, variance, optimize)
+        .into_iter()
+        .map(|(color, _)| color)
+        .collect()
+}
+
+pub fn mediancut_populations(
+    colors: &[RGBA], max_color_count: usize, sort: PaletteSort, threads: usize, variance: bool, optimize: bool,
+) -> Vec<(RGB, usize)> {
+    let mut bins = color_bins(parallel_elements(colors, threads));
+    if bins.is_empty() {
+        return Vec::new();
+    }
+
     let mut clusters = Vec::with_capacity(max_color_count);
-    let root = Cluster::form_rgba(colors);
-    clusters.push(root);
+    clusters.push(Cluster::new(&bins, 0, bins.len()));
 
     while clusters.len() < max_color_count {
-        clusters.sort_by_key(|cluster| cluster.largest_spread);
-        let mut cluster = clusters.pop().expect("mediancut get cluster");
-        clusters.append(&mut cluster.split());
-    }
+        let selected = clusters.iter()
+            .enumerate()
+            .filter(|(_, cluster)| cluster.splittable())
+            .max_by(|(_, a), (_, b)| a.priority(variance).total_cmp(&b.priority(variance)))
+            .map(|(index, _)| index);
 
-    if sort {
-        let mut palette = BTreeSet::new();
-        for cluster in &clusters {
-            palette.insert(cluster.avg());
-        }
-        palette.into_iter().collect()
-    } else {
-        let mut palette = HashSet::new();
-        for cluster in &clusters {
-            palette.insert(cluster.avg());
-        }
-        palette.into_iter().collect()
+        let Some(index) = selected else {
+            break;
+        };
+        let cluster = clusters.swap_remove(index);
+        let (left, right) = cluster.split(&mut bins, variance, optimize);
+        clusters.push(left);
+        clusters.push(right);
     }
+
+    let entries = clusters.iter()
+        .map(|cluster| (cluster.total.color(), cluster.total.count as usize))
+        .collect();
+
+    sort_entries(entries, sort)
 }
 
 // end region
 
 // region KMeans Quantizer
 
-fn init_centroids(elements: &HashMap<RGB, usize>, capacity: usize) -> HashSet<RGB> {
-    let mut set = HashSet::with_capacity(capacity);
-    let mut keys = elements.keys();
-    while set.len() < capacity {
-        if let Some(color) = keys.next() {
-            set.insert(*color);
-        } else {
+// rounding the centroids back to RGB can make them oscillate between two
+// neighbours, so a run that never settles is cut off here
+const KMEANS_LIMIT: usize = 1024;
+
+fn init_centroids(elements: &HashMap<RGB, usize>, capacity: usize) -> Vec<RGB> {
+    elements.keys()
+        .take(capacity)
+        .copied()
+        .collect()
+}
+
+fn nearest_color(centroids: &[RGB], color: &RGB) -> usize {
+    let distance = |item: &RGB| -> u32 {
+        (0..3).map(|component| (item[component] as i32 - color[component] as i32).pow(2) as u32).sum()
+    };
+
+    centroids.iter()
+        .enumerate()
+        .min_by_key(|(_, item)| distance(item))
+        .map(|(index, _)| index)
+        .expect("get nearest fail.")
+}
+
+fn refine_bins(bins: &[(RGB, Bin)], mut centroids: Vec<RGB>, iterations: usize) -> Vec<(RGB, usize)> {
+    let mut counts = vec![0usize; centroids.len()];
+
+    for _ in 0..iterations {
+        let mut clusters = vec![Bin::default(); centroids.len()];
+        for (color, bin) in bins {
+            clusters[nearest_color(&centroids, color)].add(bin);
+        }
+
+        let next: Vec<RGB> = clusters.iter()
+            .zip(centroids.iter())
+            .map(|(cluster, old)| if cluster.count > 0 { cluster.color() } else { *old })
+            .collect();
+        counts = clusters.iter().map(|cluster| cluster.count as usize).collect();
+
+        if next == centroids {
             break;
         }
+        centroids = next;
     }
 
-    set
+    centroids.into_iter().zip(counts).collect()
 }
 
-fn distinct_elements(colors: &[RGBA]) -> HashMap<RGB, usize> {
-    let mut map = HashMap::with_capacity(colors.len());
-
-    for color in colors {
-        let rgb = [color[0], color[1], color[2]];
-        let count = map.entry(rgb).or_insert(0);
-        *count = 1;
+pub fn kmeans_refine(colors: &[RGBA], palette: &[RGB], iterations: usize, threads: usize) -> Vec<RGB> {
+    if palette.is_empty() {
+        return Vec::new();
     }
 
-    map
+    let bins = color_bins(parallel_elements(colors, threads));
+
+    refine_bins(&bins, palette.to_vec(), iterations)
+        .into_iter()
+        .map(|(color, _)| color)
+        .collect()
 }
 
-fn centroid(elements: &HashMap<RGB, usize>) -> RGB {
-    let mut sum = [0usize; 3];
-    for (color, count) in elements {
-        sum[0] += color[0] as usize * count;
-        sum[1] += color[1] as usize * count;
-        sum[2] += color[2] as usize * count;
+pub fn kmeans_quantizer(colors: &[RGBA], max_color_count: usize, sort: PaletteSort, threads: usize) -> Vec<RGB> {
+    let elements = parallel_elements(colors, threads);
+    if elements.len() <= max_color_count {
+        return sort_palette(elements.into_iter().collect(), sort);
+    }
+
+    // every distinct color pulls its centroid once, however many pixels share it
+    let units: Vec<(RGB, Bin)> = elements.keys()
+        .map(|color| (*color, Bin::new(color, 1)))
+        .collect();
+    let centroids: Vec<RGB> = refine_bins(&units, init_centroids(&elements, max_color_count), KMEANS_LIMIT)
+        .into_iter()
+        .map(|(color, _)| color)
+        .collect();
+
+    let mut counts = vec![0usize; centroids.len()];
+    for (color, count) in &elements {
+        counts[nearest_color(&centroids, color)] += count;
     }
 
-    let size = elements.len();
-    [
-        (sum[0] / size) as u8,
-        (sum[1] / size) as u8,
-        (sum[2] / size) as u8,
-    ]
+    sort_palette(centroids.into_iter().zip(counts).collect(), sort)
 }
 
-fn nearest_color(centroids: &HashSet<RGB>, color: &RGB) -> RGB {
-    centroids.iter()
-        .min_by_key(|item| { item[0] * color[0] + item[1] * color[1] + item[2] * color[2] })
-        .expect("get nearest fail.")
-        .clone()
+// end region
+
+#[test]
+fn palette_sort() {
+    let entries = vec![([255, 255, 255], 1), ([0, 0, 255], 5), ([255, 0, 0], 3), ([0, 0, 0], 2)];
+
+    assert_eq!(sort_palette(entries.clone(), PaletteSort::Frequency), vec![[0, 0, 255], [255, 0, 0], [0, 0, 0], [255, 255, 255]]);
+    assert_eq!(sort_palette(entries.clone(), PaletteSort::Luminance), vec![[0, 0, 0], [0, 0, 255], [255, 0, 0], [255, 255, 255]]);
+    assert_eq!(sort_palette(entries, PaletteSort::Hue), vec![[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]]);
 }
 
-pub fn kmeans_quantizer(colors: &[RGBA], max_color_count: usize, sort: bool) -> Vec<RGB> {
-    let elements = distinct_elements(colors);
-    if elements.len() <= max_color_count {
-        return elements.keys().copied().collect();
-    }
+#[test]
+fn parallel_histogram() {
+    let colors: Vec<RGBA> = (0..PARALLEL_THRESHOLD * 2)
+        .map(|i| [(i % 251) as u8, (i % 13) as u8, (i % 7) as u8, 255])
+        .collect();
 
-    let mut centroids = init_centroids(&elements, max_color_count);
-    let rc = Rc::new(RefCell::new(HashMap::with_capacity(elements.capacity())));
+    assert_eq!(parallel_elements(&colors, 4), distinct_elements(&colors));
 
-    for (color, count) in elements {
-        let nearest = nearest_color(&centroids, &color);
-        let rc = rc.clone();
-        let mut clusters = rc.borrow_mut();
-        let record = clusters.entry(nearest)
-            .or_insert_with(|| HashMap::new());
-        record.insert(color, count);
-    }
+    let palette = octtree_populations(&colors, 16, PaletteSort::None, 4);
+    assert!(palette.len() <= 16);
+    assert_eq!(palette.iter().map(|(_, count)| count).sum::<usize>(), colors.len());
+}
 
-    while !centroids.is_empty() {
-        let rc = rc.clone();
-        let mut clusters = rc.borrow_mut();
-        for old in &centroids {
-            let cluster = clusters.remove(old)
-                .unwrap_or_else(|| HashMap::new());
-            let new = centroid(&cluster);
+#[test]
+fn octtree_reduce_all() {
+    let colors = [[1, 2, 3, 255], [4, 5, 6, 255], [1, 2, 3, 255]];
 
-            clusters.insert(new, cluster);
-        }
-        centroids.clear();
-
-        let ids = HashSet::from_iter(clusters.keys().copied());
-        for (centroid, cluster) in clusters.iter_mut() {
-            for (color, count) in cluster.clone() {
-                let new_centroid = nearest_color(&ids, &color);
-                let rc = rc.clone();
-                let mut clusters = rc.borrow_mut();
-
-                let record = clusters.entry(new_centroid)
-                    .or_insert_with(|| HashMap::new());
-                record.insert(color.clone(), count);
-                cluster.remove(&color);
-
-                centroids.insert(*centroid);
-                centroids.insert(new_centroid);
-            }
-        }
+    assert_eq!(octtree_populations(&colors, 0, PaletteSort::None, 1), vec![([2, 3, 4], 3)]);
+}
+
+#[test]
+#[ignore]
+fn octtree_benchmark() {
+    let (width, height) = (3840, 2160);
+    let mut seed = 0x2545F491u32;
+    let photo: Vec<RGBA> = (0..width * height)
+        .map(|i| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let noise = (seed >> 24) as u8 & 0x1F;
+            let x = i % width;
+            let y = i / width;
+            [(x * 255 / width) as u8 ^ noise, (y * 255 / height) as u8, ((x + y) % 256) as u8 | noise, 255]
+        })
+        .collect();
+    let screenshot: Vec<RGBA> = (0..width * height)
+        .map(|i| {
+            let x = i % width / 64;
+            let y = i / width / 32;
+            [(x * 37 % 256) as u8, (y * 91 % 256) as u8, ((x ^ y) * 13 % 256) as u8, 255]
+        })
+        .collect();
+
+    for (name, colors) in [("photo", &photo), ("screenshot", &screenshot)] {
+        let start = std::time::Instant::now();
+        let old = legacy::octtree_quantizer(colors, 256, false);
+        let legacy = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let single = octtree_quantizer(colors, 256, PaletteSort::None, 1);
+        let arena = start.elapsed();
+
+        let start = std::time::Instant::now();
+        octtree_quantizer(colors, 256, PaletteSort::None, 0);
+        let parallel = start.elapsed();
+
+        let (_, _, old_psnr) = super::palette::quantization_error(colors, &old);
+        let (_, _, psnr) = super::palette::quantization_error(colors, &single);
+
+        println!("{}: legacy {:?} ({} colors, {:.2} dB)", name, legacy, old.len(), old_psnr);
+        println!("{}: arena {:?} ({} colors, {:.2} dB, {:.1}x)", name, arena, single.len(), psnr, legacy.as_secs_f64() / arena.as_secs_f64());
+        println!("{}: arena parallel {:?} ({:.1}x)", name, parallel, legacy.as_secs_f64() / parallel.as_secs_f64());
     }
+}
 
-    let clusters = rc.borrow();
+#[test]
+fn mediancut_split() {
+    let colors: Vec<RGBA> = (0..4096)
+        .map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 0, 255])
+        .collect();
 
-    if sort {
-        BTreeSet::from_iter(clusters.keys().copied()).into_iter().collect()
-    } else {
-        clusters.keys().copied().collect()
+    for (variance, optimize) in [(false, false), (true, false), (true, true)] {
+        let palette = mediancut_populations(&colors, 16, PaletteSort::None, 1, variance, optimize);
+        assert_eq!(palette.len(), 16);
+        assert_eq!(palette.iter().map(|(_, count)| count).sum::<usize>(), colors.len());
     }
+
+    let few = [[1, 2, 3, 255], [4, 5, 6, 255], [1, 2, 3, 255]];
+    assert_eq!(mediancut_populations(&few, 16, PaletteSort::RGB, 1, true, true), vec![([1, 2, 3], 2), ([4, 5, 6], 1)]);
+
+    let close = [[0, 0, 0, 255], [1, 1, 1, 255], [1, 1, 1, 255]];
+    assert_eq!(mediancut_populations(&close, 2, PaletteSort::RGB, 1, false, false), vec![([0, 0, 0], 1), ([1, 1, 1], 2)]);
 }
 
-// end region
\ No newline at end of file
+#[test]
+fn kmeans_refine_converges() {
+    let colors: Vec<RGBA> = (0..1024)
+        .map(|i| if i % 2 == 0 { [10, 20, 30, 255] } else { [200, 210, 220, 255] })
+        .collect();
+
+    assert_eq!(kmeans_refine(&colors, &[[0, 0, 0], [255, 255, 255]], 8, 1), vec![[10, 20, 30], [200, 210, 220]]);
+    assert_eq!(kmeans_quantizer(&colors, 1, PaletteSort::None, 1), vec![[105, 115, 125]]);
+
+    let close = [[0, 0, 0, 255], [3, 3, 3, 255], [3, 3, 3, 255]];
+    assert_eq!(kmeans_refine(&close, &[[0, 0, 0], [4, 4, 4]], 8, 1), vec![[0, 0, 0], [3, 3, 3]]);
+}
+
+#[test]
+fn kmeans_distinct_weight() {
+    let colors: Vec<RGBA> = (0..1024)
+        .map(|i| if i % 4 == 0 { [40, 40, 40, 255] } else { [0, 0, 0, 255] })
+        .collect();
+
+    assert_eq!(kmeans_quantizer(&colors, 1, PaletteSort::None, 1), vec![[20, 20, 20]]);
+}
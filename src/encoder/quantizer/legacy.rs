@@ -0,0 +1,246 @@
+// The Rc<RefCell<Node>> OctTree from 2.0.8, kept only to benchmark the arena implementation against.
+// The code is left as it shipped, so its lints are allowed rather than fixed.
+#![allow(clippy::manual_flatten, clippy::unnecessary_cast)]
+
+use std::cell::*;
+use std::collections::*;
+use std::ops::DerefMut;
+use std::rc::Rc;
+
+type RGBA = [u8; 4];
+
+type RGB = [u8; 3];
+
+type Link = Rc<RefCell<Node>>;
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        red_sum: u32,
+        green_sum: u32,
+        blue_sum: u32,
+        pixel_count: u32,
+    },
+    Branch {
+        children: [Option<Link>; 8],
+    },
+}
+
+impl Node {
+    fn value(&self) -> (u32, u32, u32, u32) {
+        match self {
+            Node::Leaf {
+                red_sum,
+                green_sum,
+                blue_sum,
+                pixel_count,
+            } => (*red_sum, *green_sum, *blue_sum, *pixel_count),
+            Node::Branch { .. } => (0, 0, 0, 0),
+        }
+    }
+
+    fn palette_sort(&self, colors: &mut BTreeSet<RGB>) {
+        match self {
+            Node::Leaf {
+                red_sum,
+                green_sum,
+                blue_sum,
+                pixel_count,
+            } => {
+                colors.insert([
+                    ((red_sum / pixel_count) & 0xFF) as u8,
+                    ((green_sum / pixel_count) & 0xFF) as u8,
+                    ((blue_sum / pixel_count) & 0xFF) as u8
+                ]);
+            }
+            Node::Branch { children } => {
+                for child in children.iter() {
+                    if let Some(link) = child {
+                        link.try_borrow_mut()
+                            .expect("palette get node")
+                            .palette_sort(colors);
+                    }
+                }
+            }
+        }
+    }
+
+    fn palette(&self, colors: &mut HashSet<RGB>) {
+        match self {
+            Node::Leaf {
+                red_sum,
+                green_sum,
+                blue_sum,
+                pixel_count,
+            } => {
+                colors.insert([
+                    ((red_sum / pixel_count) & 0xFF) as u8,
+                    ((green_sum / pixel_count) & 0xFF) as u8,
+                    ((blue_sum / pixel_count) & 0xFF) as u8
+                ]);
+            }
+            Node::Branch { children } => {
+                for child in children.iter() {
+                    if let Some(link) = child {
+                        link.try_borrow_mut()
+                            .expect("palette get node")
+                            .palette(colors);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OctTree {
+    leaf_count: usize,
+    root: Option<Link>,
+    node_list: [Vec<Link>; 8],
+}
+
+const MASK: [u8; 8] = [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01];
+
+impl OctTree {
+    fn new() -> Self {
+        let mut tree = OctTree {
+            leaf_count: 0,
+            root: None,
+            node_list: MASK.map(|_| Vec::new()),
+        };
+
+        tree.root = Some(tree.create_node(0));
+
+        tree
+    }
+
+    fn create_node(&mut self, level: usize) -> Link {
+        if level == 8 {
+            self.leaf_count += 1;
+
+            let node = Node::Leaf {
+                red_sum: 0,
+                green_sum: 0,
+                blue_sum: 0,
+                pixel_count: 0,
+            };
+
+            Rc::new(RefCell::new(node))
+        } else {
+            let node = Node::Branch {
+                children: MASK.map(|_| None as Option<Link>),
+            };
+            let link = Rc::new(RefCell::new(node));
+            self.node_list[level].push(link.clone());
+
+            link
+        }
+    }
+
+    fn add_color(&mut self, link: &mut Link, color: &RGBA, in_level: usize) {
+        let mut node: RefMut<Node> = link.try_borrow_mut().expect("add_color get node");
+
+        match node.deref_mut() {
+            Node::Leaf {
+                pixel_count,
+                red_sum,
+                green_sum,
+                blue_sum,
+                ..
+            } => {
+                *pixel_count += 1;
+                *red_sum += color[0] as u32;
+                *green_sum += color[1] as u32;
+                *blue_sum += color[2] as u32;
+            }
+            Node::Branch { children, .. } => {
+                let shift = 7 - in_level;
+                let mask = MASK[in_level];
+                let n_index = (((color[0] & mask) as usize >> shift << 2)
+                    | ((color[1] & mask) as usize >> shift << 1)
+                    | ((color[2] & mask) as usize >> shift)) as usize;
+
+                let link = children[n_index]
+                    .get_or_insert_with(|| self.create_node(in_level + 1));
+
+                self.add_color(link, color, in_level + 1)
+            }
+        }
+    }
+
+    fn reduce_tree(&mut self) {
+        let mut red_sum: u32 = 0;
+        let mut green_sum: u32 = 0;
+        let mut blue_sum: u32 = 0;
+        let mut pixel_count: u32 = 0;
+
+        let link = self
+            .node_list
+            .iter_mut()
+            .rfind(|vec| !vec.is_empty())
+            .expect("reduce_tree get vec")
+            .pop()
+            .expect("reduce_tree get link");
+
+        let mut current = link.try_borrow_mut().expect("reduce_tree get current");
+
+        match current.deref_mut() {
+            Node::Leaf { .. } => {}
+            Node::Branch { children, .. } => {
+                for child in children.iter() {
+                    if let Some(link) = child {
+                        let (red, green, blue, pixel) = link.try_borrow()
+                            .expect("get link value")
+                            .value();
+                        red_sum += red;
+                        green_sum += green;
+                        blue_sum += blue;
+                        pixel_count += pixel;
+
+                        self.leaf_count -= 1
+                    }
+                }
+            }
+        }
+
+        let leaf = Node::Leaf {
+            red_sum,
+            green_sum,
+            blue_sum,
+            pixel_count,
+        };
+
+        *current = leaf;
+
+        self.leaf_count += 1
+    }
+
+    fn color_palette(&self, sort: bool) -> Vec<RGB> {
+        let node: Ref<Node> = self.root.as_ref().unwrap().try_borrow().unwrap();
+
+        if sort {
+            let mut palette = BTreeSet::new();
+            node.palette_sort(&mut palette);
+            palette.into_iter().collect()
+        } else {
+            let mut palette = HashSet::new();
+            node.palette(&mut palette);
+            palette.into_iter().collect()
+        }
+    }
+}
+
+pub fn octtree_quantizer(colors: &[RGBA], max_color_count: usize, sort: bool) -> Vec<RGB> {
+    let mut tree = OctTree::new();
+    let mut root = tree.root.clone().unwrap();
+
+    for color in colors {
+        tree.add_color(&mut root, color, 0);
+
+        while tree.leaf_count > max_color_count {
+            tree.reduce_tree()
+        }
+    }
+
+    tree.color_palette(sort)
+}
@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use gif::*;
+use super::interlace::interlaced_frame;
+use super::lossy::lossy_frame;
+use crate::decoder::metadata::*;
+use crate::source::resize::*;
+
+pub struct Writer<W: Write> {
+    pub encoder: Encoder<W>,
+    pub width: u16,
+    pub height: u16,
+    pub resize: Resize,
+    pub sampling: Sampling,
+    pub interlaced: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(w: W, width: u16, height: u16, global_palette: &[u8]) -> Result<Self, EncodingError> {
+        let encoder = Encoder::new(w, width, height, global_palette)?;
+
+        Ok(Writer {
+            encoder,
+            width,
+            height,
+            resize: Resize::None,
+            sampling: Sampling::Mitchell,
+            interlaced: false,
+        })
+    }
+
+    pub fn placement(&self, width: i32, height: i32) -> Option<Placement> {
+        placement(self.resize, (width.max(0) as _, height.max(0) as _), (self.width, self.height))
+    }
+
+    /// Writes a frame whose buffer is in display order, interlacing it when either the writer
+    /// or the frame asks for it.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), EncodingError> {
+        self.write_lossy_frame(frame, 0)
+    }
+
+    /// Writes a frame like `write_frame`, running the lossy pass after interlacing so that it
+    /// follows the rows in the order they are compressed.
+    pub fn write_lossy_frame(&mut self, frame: &Frame, lossy: u32) -> Result<(), EncodingError> {
+        if frame.buffer.len() != frame.width as usize * frame.height as usize {
+            let message = format!("frame buffer of {} bytes does not match its {}x{} rect", frame.buffer.len(), frame.width, frame.height);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+
+        let mut wire = if self.interlaced || frame.interlaced {
+            Cow::Owned(interlaced_frame(frame))
+        } else {
+            Cow::Borrowed(frame)
+        };
+        if lossy > 0 {
+            lossy_frame(wire.to_mut(), None, lossy);
+        }
+
+        self.encoder.write_frame(&wire)
+    }
+
+    /// Empty comments are skipped, the `gif` decoder fails on extensions without sub-blocks.
+    pub fn write_comment(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.encoder.write_raw_extension(Extension::Comment.into(), &[text.as_bytes()])
+    }
+
+    /// `identifier` is the 8 byte application identifier followed by the 3 byte authentication code.
+    pub fn write_application(&mut self, identifier: &[u8; 11], data: &[u8]) -> io::Result<()> {
+        self.encoder.write_raw_extension(Extension::Application.into(), &[identifier, data])
+    }
+
+    /// XMP packets are stored as raw bytes rather than sub-blocks, so this bypasses `write_raw_extension`.
+    pub fn write_xmp(&mut self, xmp: &str) -> io::Result<()> {
+        let w = self.encoder.get_mut();
+        w.write_all(&[Block::Extension as u8, Extension::Application as u8, XMP_IDENTIFIER.len() as u8])?;
+        w.write_all(XMP_IDENTIFIER)?;
+        w.write_all(xmp.as_bytes())?;
+        w.write_all(&xmp_trailer().collect::<Vec<u8>>())?;
+        w.write_all(&[0])
+    }
+}
+
+#[test]
+fn write_frame_rect() {
+    let mut writer = Writer::new(Vec::new(), 8, 8, &[]).unwrap();
+    writer.interlaced = true;
+
+    let mut frame = Frame::from_palette_pixels(4, 4, &[0; 16], &[0, 0, 0, 255, 255, 255], None);
+    frame.width = 8;
+    let error = writer.write_frame(&frame).unwrap_err();
+    assert!(matches!(error, EncodingError::Io(error) if error.kind() == io::ErrorKind::InvalidInput));
+
+    frame.width = 4;
+    writer.write_lossy_frame(&frame, 20).unwrap();
+}
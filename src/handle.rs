@@ -0,0 +1,208 @@
+//! Handles given to Java are ids into a registry rather than raw pointers, so null, closed and
+//! wrong-type handles raise `IllegalStateException` instead of touching freed memory.
+//!
+//! Every encoder, frame, decoder, pipeline and budget handle is guarded by its own mutex. Natives on
+//! different handles run in parallel, natives on the same handle must not overlap: a call that finds
+//! its handle busy throws `IllegalStateException` rather than waiting, since overlapping writes on one
+//! encoder would interleave frames anyway. A native taking two handles, like `Encoder.writeFrame`,
+//! holds both, so one frame written to two encoders at once fails as well.
+//!
+//! `EncodeJob.cancel` is the exception, it may be called at any time from any thread, including while
+//! another thread is blocked in `EncodeJob.writeImage`. Natives without a handle (quantizers,
+//! ditherers, palette and source settings) only read their arguments and are safe to call concurrently.
+
+use std::any::{type_name, Any};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, TryLockError};
+use std::sync::atomic::{AtomicI64, Ordering};
+use jni::JNIEnv;
+use jni::sys::*;
+
+pub const ILLEGAL_ARGUMENT: &str = "java/lang/IllegalArgumentException";
+
+pub const ILLEGAL_STATE: &str = "java/lang/IllegalStateException";
+
+pub const NULL_POINTER: &str = "java/lang/NullPointerException";
+
+pub const IO_EXCEPTION: &str = "java/io/IOException";
+
+type Entry = Arc<dyn Any + Send + Sync>;
+
+/// Every live handle handed to Java, ids are never reused so a stale handle cannot reach a newer object.
+static HANDLES: OnceLock<Mutex<HashMap<jlong, Entry>>> = OnceLock::new();
+
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+fn handles() -> MutexGuard<'static, HashMap<jlong, Entry>> {
+    HANDLES.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// What a native returns after throwing, Java ignores it while the exception is pending.
+pub trait Fallback {
+    fn fallback() -> Self;
+}
+
+impl Fallback for () {
+    fn fallback() -> Self {}
+}
+
+impl Fallback for bool {
+    fn fallback() -> Self { false }
+}
+
+impl Fallback for jboolean {
+    fn fallback() -> Self { 0 }
+}
+
+impl Fallback for jint {
+    fn fallback() -> Self { 0 }
+}
+
+impl Fallback for jlong {
+    fn fallback() -> Self { 0 }
+}
+
+impl<T> Fallback for *mut T {
+    fn fallback() -> Self { std::ptr::null_mut() }
+}
+
+impl<T> Fallback for Option<T> {
+    fn fallback() -> Self { None }
+}
+
+pub fn throw<R: Fallback>(env: JNIEnv, class: &str, message: impl AsRef<str>) -> R {
+    // the first exception is the one Java sees, later ones would only replace it
+    if !env.exception_check().unwrap_or(true) {
+        let _ = env.throw_new(class, message.as_ref());
+    }
+    R::fallback()
+}
+
+fn register<S: Any + Send + Sync>(value: S) -> jlong {
+    let id = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    handles().insert(id, Arc::new(value));
+
+    id
+}
+
+fn lookup<S: Any + Send + Sync>(ptr: jlong, name: &str) -> Result<Arc<S>, String> {
+    if ptr == 0 {
+        return Err(format!("{name} handle is null"));
+    }
+    let entry = handles().get(&ptr)
+        .cloned()
+        .ok_or_else(|| format!("{name} handle {ptr:#x} is closed or was never opened"))?;
+
+    entry.downcast::<S>()
+        .map_err(|_| format!("handle {ptr:#x} is not a {name}"))
+}
+
+fn remove<S: Any + Send + Sync>(ptr: jlong, name: &str) -> Result<Arc<S>, String> {
+    let entry = lookup::<S>(ptr, name)?;
+    handles().remove(&ptr);
+
+    Ok(entry)
+}
+
+pub fn lock<T, R: Fallback>(env: JNIEnv, mutex: &Mutex<T>, name: &str, block: impl FnOnce(&mut T) -> R) -> R {
+    match mutex.try_lock() {
+        Ok(mut guard) => block(&mut guard),
+        Err(TryLockError::WouldBlock) => throw(env, ILLEGAL_STATE, format!("{name} is in use by another thread")),
+        Err(TryLockError::Poisoned(_)) => throw(env, ILLEGAL_STATE, format!("{name} was left broken by an earlier failure")),
+    }
+}
+
+/// Registers the value as a handle of type `T`, behind its own mutex.
+pub fn into_handle<T: Send + 'static>(value: T) -> jlong {
+    register(Mutex::new(value))
+}
+
+/// Registers a value that synchronizes itself, for natives which must not wait on a handle lock.
+pub fn into_shared_handle<T: Send + Sync + 'static>(value: T) -> jlong {
+    register(value)
+}
+
+/// Runs `block` on the value behind a live, idle handle of type `T`, throwing `IllegalStateException` otherwise.
+pub fn with_handle<T: Send + 'static, R: Fallback>(env: JNIEnv, ptr: jlong, block: impl FnOnce(&mut T) -> R) -> R {
+    let name = type_name::<T>();
+    match lookup::<Mutex<T>>(ptr, name) {
+        Ok(entry) => lock(env, &entry, &format!("{name} handle {ptr:#x}"), block),
+        Err(message) => throw(env, ILLEGAL_STATE, message),
+    }
+}
+
+pub fn with_shared_handle<T: Send + Sync + 'static, R: Fallback>(env: JNIEnv, ptr: jlong, block: impl FnOnce(&T) -> R) -> R {
+    match lookup::<T>(ptr, type_name::<T>()) {
+        Ok(entry) => block(&entry),
+        Err(message) => throw(env, ILLEGAL_STATE, message),
+    }
+}
+
+/// Unregisters the handle and hands the value back, `None` after throwing.
+/// A handle still in use by another thread stays registered.
+pub fn take_handle<T: Send + 'static>(env: JNIEnv, ptr: jlong) -> Option<T> {
+    let name = type_name::<T>();
+    let entry = match remove::<Mutex<T>>(ptr, name) {
+        Ok(entry) => entry,
+        Err(message) => return throw(env, ILLEGAL_STATE, message),
+    };
+
+    match Arc::try_unwrap(entry) {
+        Ok(mutex) => Some(mutex.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())),
+        Err(entry) => {
+            handles().insert(ptr, entry);
+            throw(env, ILLEGAL_STATE, format!("{name} handle {ptr:#x} is in use by another thread"))
+        }
+    }
+}
+
+/// Unregisters a shared handle, calls already running on it keep their reference.
+pub fn take_shared_handle<T: Send + Sync + 'static>(env: JNIEnv, ptr: jlong) -> Option<Arc<T>> {
+    match remove::<T>(ptr, type_name::<T>()) {
+        Ok(entry) => Some(entry),
+        Err(message) => throw(env, ILLEGAL_STATE, message),
+    }
+}
+
+/// Throws `IllegalArgumentException` unless `len` bytes hold exactly `width * height` pixels of `channels` bytes.
+pub fn check_size(env: JNIEnv, width: jint, height: jint) -> bool {
+    if !(1..=u16::MAX as jint).contains(&width) || !(1..=u16::MAX as jint).contains(&height) {
+        return throw(env, ILLEGAL_ARGUMENT, format!("size {width}x{height} is out of the range [1, 65535]"));
+    }
+
+    true
+}
+
+pub fn check_pixels(env: JNIEnv, width: jint, height: jint, channels: usize, len: usize) -> bool {
+    if !check_size(env, width, height) {
+        return false;
+    }
+    let expected = width as usize * height as usize * channels;
+    if len != expected {
+        return throw(env, ILLEGAL_ARGUMENT, format!("buffer of {len} bytes does not hold {width}x{height} pixels, {expected} bytes expected"));
+    }
+
+    true
+}
+
+#[test]
+fn handle_registry() {
+    let ptr = into_handle(String::from("frame"));
+    let name = type_name::<String>();
+
+    assert!(lookup::<Mutex<String>>(ptr, name).is_ok());
+    assert!(lookup::<Mutex<Vec<u8>>>(ptr, name).unwrap_err().contains("is not a"));
+    assert!(lookup::<Mutex<String>>(0, name).unwrap_err().contains("null"));
+
+    // a second caller finds the handle busy instead of aliasing it
+    let entry = lookup::<Mutex<String>>(ptr, name).unwrap();
+    let guard = entry.try_lock().unwrap();
+    assert!(matches!(lookup::<Mutex<String>>(ptr, name).unwrap().try_lock(), Err(TryLockError::WouldBlock)));
+    drop(guard);
+
+    assert!(remove::<Mutex<String>>(ptr, name).is_ok());
+    assert!(lookup::<Mutex<String>>(ptr, name).unwrap_err().contains("closed"));
+    assert_ne!(into_handle(String::new()), ptr);
+}
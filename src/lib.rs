@@ -1,483 +1,1477 @@
 extern crate core;
 
+mod decoder;
 mod encoder;
+mod handle;
+mod natives;
+mod source;
 mod style;
 
+use std::borrow::Cow;
 use std::fs::File;
 use std::slice;
 use gif::*;
 use jni::JNIEnv;
-use jni::objects::JString;
+use jni::objects::{JByteBuffer, JObject, JString, ReleaseMode};
 use jni::sys::*;
 use skia_safe::*;
-use skia_safe::image::*;
 use skia_safe::wrapper::*;
+use decoder::reader::*;
+use encoder::budget::*;
 use encoder::quantizer::*;
 use encoder::ditherer::*;
+use encoder::frame;
+use encoder::job::*;
+use encoder::lossy::*;
+use encoder::palette::*;
+use encoder::pipeline::*;
+use encoder::writer::*;
+use handle::*;
+use source::convert::Layout;
+use source::reader::*;
+use source::resize::*;
 use style::lowpoly::*;
 
+// region Pixel Source
+
+fn read_bitmap<R>(_env: JNIEnv, bitmap_ptr: jlong, block: impl FnOnce(&[[u8; 4]], i32, i32) -> R) -> R {
+    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
+    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
+
+    let result = match pixmap_pixels(bitmap.pixmap()) {
+        Some(pixels) => block(&pixels, bitmap.width(), bitmap.height()),
+        None => _env.fatal_error("get pixels fail."),
+    };
+
+    sk_bitmap.unwrap();
+    result
+}
+
+fn read_image<R>(_env: JNIEnv, image_ptr: jlong, block: impl FnOnce(&[[u8; 4]], i32, i32) -> R) -> R {
+    let image = Image::wrap(image_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap image fail."));
+    let (width, height) = (image.width(), image.height());
+
+    let result = image_pixels(&image, |pixels| block(pixels, width, height))
+        .unwrap_or_else(|| _env.fatal_error("read pixels fail."));
+
+    image.unwrap();
+    result
+}
+
+fn read_pixmap<R>(_env: JNIEnv, pixmap_ptr: jlong, block: impl FnOnce(&[[u8; 4]], i32, i32) -> R) -> R {
+    let sk_pixmap = RefHandle::wrap(pixmap_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap SkPixmap"));
+    let pixmap = Pixmap::wrap_ref(sk_pixmap.inner());
+
+    let result = match pixmap_pixels(pixmap) {
+        Some(pixels) => block(&pixels, pixmap.width(), pixmap.height()),
+        None => _env.fatal_error("get pixels fail."),
+    };
+
+    sk_pixmap.unwrap();
+    result
+}
+
+fn canvas_image(
+    _env: JNIEnv, image_ptr: jlong, placement: impl FnOnce(i32, i32) -> Option<Placement>, sampling: Sampling,
+) -> (Vec<u8>, [u16; 4]) {
+    let image = Image::wrap(image_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap image fail."));
+    let (width, height) = (image.width(), image.height());
+
+    let result = match placement(width, height) {
+        Some(placement) => resample_pixels(&image, &placement, sampling, |pixels| (pixels.concat(), placement.frame)),
+        None => image_pixels(&image, |pixels| (pixels.concat(), [0, 0, width as _, height as _])),
+    }.unwrap_or_else(|| _env.fatal_error("read pixels fail."));
+
+    image.unwrap();
+    result
+}
+
+fn canvas_bitmap(
+    _env: JNIEnv, bitmap_ptr: jlong, placement: impl FnOnce(i32, i32) -> Option<Placement>, sampling: Sampling,
+) -> (Vec<u8>, [u16; 4]) {
+    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
+    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
+    let (width, height) = (bitmap.width(), bitmap.height());
+
+    let result = match placement(width, height) {
+        Some(placement) => resample_pixels(&bitmap.as_image(), &placement, sampling, |pixels| (pixels.concat(), placement.frame)),
+        None => pixmap_pixels(bitmap.pixmap()).map(|pixels| (pixels.concat(), [0, 0, width as _, height as _])),
+    }.unwrap_or_else(|| _env.fatal_error("get pixels fail."));
+
+    sk_bitmap.unwrap();
+    result
+}
+
+fn rgba_frame(pixels: &mut [u8], rect: [u16; 4], speed: i32) -> Frame<'static> {
+    let [left, top, width, height] = rect;
+    let mut frame = Frame::from_rgba_speed(width, height, pixels, speed);
+
+    frame.left = left;
+    frame.top = top;
+    frame
+}
+
+fn with_palette<R: Fallback>(_env: JNIEnv, palette_ptr: jlong, block: impl FnOnce(&[[u8; 3]]) -> R) -> R {
+    let data = Data::wrap(palette_ptr as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
+    let palette = unsafe { slice::from_raw_parts(data.as_ptr() as _, data.len() / 3) };
+
+    // every caller looks colors up in the palette, and an index has to fit in a byte
+    let result = if (1..=256).contains(&palette.len()) {
+        block(palette)
+    } else {
+        throw(_env, ILLEGAL_ARGUMENT, format!("palette of {} colors is not in the range [1, 256]", palette.len()))
+    };
+
+    data.unwrap();
+    result
+}
+
+fn with_byte_array<R: Fallback>(_env: JNIEnv, array: jbyteArray, block: impl FnOnce(&[u8]) -> R) -> R {
+    if array.is_null() {
+        return throw(_env, NULL_POINTER, "array is null");
+    }
+    // nothing is written back, so a copying JVM can drop its copy on release
+    let elements = _env.get_byte_array_elements(array, ReleaseMode::NoCopyBack)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let size = elements.size()
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let bytes = unsafe { slice::from_raw_parts(elements.as_ptr() as *const u8, size as _) };
+
+    block(bytes)
+}
+
+fn with_direct_buffer<R: Fallback>(_env: JNIEnv, buffer: JByteBuffer, block: impl FnOnce(&[u8]) -> R) -> R {
+    if buffer.is_null() {
+        return throw(_env, NULL_POINTER, "buffer is null");
+    }
+    // heap buffers have no address, Java callers have to wrap them with the byte[] overloads
+    let address = match _env.get_direct_buffer_address(buffer) {
+        Ok(address) => address,
+        Err(error) => return throw(_env, ILLEGAL_ARGUMENT, format!("expected a direct buffer: {}", error)),
+    };
+    let capacity = _env.get_direct_buffer_capacity(buffer)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let bytes = unsafe { slice::from_raw_parts(address as *const u8, capacity) };
+
+    block(bytes)
+}
+
+/// The `sort` flag of the first quantizer natives, which ordered the palette by RGB.
+fn legacy_sort(sort: jboolean) -> PaletteSort {
+    if sort == JNI_TRUE { PaletteSort::RGB } else { PaletteSort::None }
+}
+
+fn palette_sort(_env: JNIEnv, value: jint) -> Option<PaletteSort> {
+    match PaletteSort::from_i32(value) {
+        Some(sort) => Some(sort),
+        None => throw(_env, ILLEGAL_ARGUMENT, format!("unknown palette sort {}", value)),
+    }
+}
+
+fn color_count(_env: JNIEnv, count: jint) -> Option<usize> {
+    if (1..=256).contains(&count) {
+        Some(count as usize)
+    } else {
+        throw(_env, ILLEGAL_ARGUMENT, format!("color count {} is not in the range [1, 256]", count))
+    }
+}
+
+/// Rejected input becomes an `IllegalArgumentException`, a failing stream an `IOException`.
+fn encoding_error<R: Fallback>(_env: JNIEnv, error: EncodingError) -> R {
+    match error {
+        EncodingError::Io(error) if error.kind() != std::io::ErrorKind::InvalidInput => throw(_env, IO_EXCEPTION, error.to_string()),
+        error => throw(_env, ILLEGAL_ARGUMENT, error.to_string()),
+    }
+}
+
+/// Unreadable files and malformed GIF data both surface as an `IOException`.
+fn decoding_error<R: Fallback>(_env: JNIEnv, error: DecodingError) -> R {
+    throw(_env, IO_EXCEPTION, error.to_string())
+}
+
+fn check_speed(_env: JNIEnv, speed: jint) -> bool {
+    if !(1..=30).contains(&speed) {
+        return throw(_env, ILLEGAL_ARGUMENT, format!("speed {} is not in the range [1, 30]", speed));
+    }
+
+    true
+}
+
+fn dispose_method(_env: JNIEnv, value: jint) -> Option<DisposalMethod> {
+    match DisposalMethod::from_u8(value as _).filter(|_| (0..=3).contains(&value)) {
+        Some(dispose) => Some(dispose),
+        None => throw(_env, ILLEGAL_ARGUMENT, format!("unknown dispose method {}", value)),
+    }
+}
+
+fn palette_data(palette: &[[u8; 3]]) -> jlong {
+    let bytes = unsafe { slice::from_raw_parts(palette.as_ptr() as _, palette.len() * 3) };
+
+    Data::new_copy(bytes).unwrap() as _
+}
+
+fn dithered_data(colors: &[[u8; 4]]) -> jlong {
+    let bytes = unsafe { slice::from_raw_parts(colors.as_ptr() as _, colors.len() * 3) };
+
+    Data::new_copy(bytes).unwrap() as _
+}
+
+fn entries_array(_env: JNIEnv, mut buf: Vec<jint>, entries: Vec<([u8; 3], usize)>) -> jintArray {
+    for (color, count) in entries {
+        buf.push(((color[0] as jint) << 16) | ((color[1] as jint) << 8) | color[2] as jint);
+        buf.push(count as jint);
+    }
+    let arr = _env.new_int_array(buf.len() as _)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+
+    _env.set_int_array_region(arr, 0, &buf)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+
+    arr
+}
+
+// endregion
+
+// region GIF Library
+
+/// Properties text, `key=value` per line with comma separated lists, describing what this build can do.
+fn library_info() -> String {
+    let layouts: Vec<String> = Layout::ALL.iter().map(|layout| format!("{layout:?}")).collect();
+    [
+        ("version", env!("CARGO_PKG_VERSION").to_owned()),
+        ("gif", env!("GIF_VERSION").to_owned()),
+        ("skia-safe", env!("SKIA_SAFE_VERSION").to_owned()),
+        ("quantizers", "OctTree,MedianCut,KMeans".to_owned()),
+        ("ditherers", "Atkinson,JJN,SierraLite,Stucki".to_owned()),
+        ("styles", "LowPoly".to_owned()),
+        ("formats", layouts.join(",")),
+        ("pixels", "Indexed,Palette,RGB,RGBA".to_owned()),
+    ].iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Library_info(
+    _env: JNIEnv, _this: jclass,
+) -> jstring {
+    _env.new_string(library_info())
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()))
+        .into_raw()
+}
+
+// endregion
+
+// region GIF Source
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Source_getConvertSRGB(
+    _env: JNIEnv, _this: jclass,
+) -> jboolean {
+    convert_srgb() as _
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Source_setConvertSRGB(
+    _env: JNIEnv, _this: jclass, enable: jboolean,
+) {
+    set_convert_srgb(enable != 0)
+}
+
+// endregion
+
 // region GIF Quantizer
 
+fn octtree_native(_env: JNIEnv, bitmap_ptr: jlong, count: jint, sort: PaletteSort, threads: jint) -> jlong {
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+        octtree_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+// `native` is overloaded, so every overload is exported under its long JNI name
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JIZ(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
+) -> jlong {
+    octtree_native(_env, bitmap_ptr, count, legacy_sort(sort), 1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    octtree_native(_env, bitmap_ptr, count, sort, 1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JIII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    octtree_native(_env, bitmap_ptr, count, sort, threads)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_image(_env, image_ptr, |pixels, _, _| {
+        octtree_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+        octtree_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+fn octtree_populations_native(_env: JNIEnv, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+        octtree_populations(pixels, count, sort, threads.max(0) as _)
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populations__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint,
+) -> jintArray {
+    octtree_populations_native(_env, bitmap_ptr, count, sort, 1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populations__JIII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jintArray {
+    octtree_populations_native(_env, bitmap_ptr, count, sort, threads)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populationsImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_image(_env, image_ptr, |pixels, _, _| {
+        octtree_populations(pixels, count, sort, threads.max(0) as _)
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populationsPixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+        octtree_populations(pixels, count, sort, threads.max(0) as _)
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+fn mediancut_native(
+    _env: JNIEnv, bitmap_ptr: jlong, count: jint, sort: PaletteSort, threads: jint, variance: jboolean, optimize: jboolean,
+) -> jlong {
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+        mediancut_quantizer(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIZ(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
+) -> jlong {
+    mediancut_native(_env, bitmap_ptr, count, legacy_sort(sort), 1, JNI_FALSE, JNI_FALSE)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    mediancut_native(_env, bitmap_ptr, count, sort, 1, JNI_FALSE, JNI_FALSE)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    mediancut_native(_env, bitmap_ptr, count, sort, threads, JNI_FALSE, JNI_FALSE)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIIIZZ(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    mediancut_native(_env, bitmap_ptr, count, sort, threads, variance, optimize)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_image(_env, image_ptr, |pixels, _, _| {
+        mediancut_quantizer(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+        mediancut_quantizer(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    palette_data(&palette)
+}
+
+fn mediancut_populations_native(
+    _env: JNIEnv, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint, variance: jboolean, optimize: jboolean,
+) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+        mediancut_populations(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint,
+) -> jintArray {
+    mediancut_populations_native(_env, bitmap_ptr, count, sort, 1, JNI_FALSE, JNI_FALSE)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JIII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jintArray {
+    mediancut_populations_native(_env, bitmap_ptr, count, sort, threads, JNI_FALSE, JNI_FALSE)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JIIIZZ(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jintArray {
+    mediancut_populations_native(_env, bitmap_ptr, count, sort, threads, variance, optimize)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populationsImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_image(_env, image_ptr, |pixels, _, _| {
+        mediancut_populations(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populationsPixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+    variance: jboolean, optimize: jboolean,
+) -> jintArray {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let entries = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+        mediancut_populations(
+            pixels,
+            count,
+            sort,
+            threads.max(0) as _,
+            variance == JNI_TRUE,
+            optimize == JNI_TRUE,
+        )
+    });
+
+    entries_array(_env, Vec::new(), entries)
+}
+
+fn kmeans_native(_env: JNIEnv, bitmap_ptr: jlong, count: jint, sort: PaletteSort, threads: jint) -> jlong {
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+        kmeans_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JIZ(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
+) -> jlong {
+    kmeans_native(_env, bitmap_ptr, count, legacy_sort(sort), 1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    kmeans_native(_env, bitmap_ptr, count, sort, 1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JIII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    kmeans_native(_env, bitmap_ptr, count, sort, threads)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_image(_env, image_ptr, |pixels, _, _| {
+        kmeans_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, count: jint, sort: jint, threads: jint,
+) -> jlong {
+    let Some(sort) = palette_sort(_env, sort) else { return Fallback::fallback() };
+    let Some(count) = color_count(_env, count) else { return Fallback::fallback() };
+    let palette = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+        kmeans_quantizer(pixels, count, sort, threads.max(0) as _)
+    });
+
+    palette_data(&palette)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_refine(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong, iterations: jint, threads: jint,
+) -> jlong {
+    with_palette(_env, palette_ptr, |seed| {
+        let palette = read_bitmap(_env, bitmap_ptr, |pixels, _, _| {
+            kmeans_refine(pixels, seed, iterations.max(0) as _, threads.max(0) as _)
+        });
+
+        palette_data(&palette)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_refineImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, palette_ptr: jlong, iterations: jint, threads: jint,
+) -> jlong {
+    with_palette(_env, palette_ptr, |seed| {
+        let palette = read_image(_env, image_ptr, |pixels, _, _| {
+            kmeans_refine(pixels, seed, iterations.max(0) as _, threads.max(0) as _)
+        });
+
+        palette_data(&palette)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_refinePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, palette_ptr: jlong, iterations: jint, threads: jint,
+) -> jlong {
+    with_palette(_env, palette_ptr, |seed| {
+        let palette = read_pixmap(_env, pixmap_ptr, |pixels, _, _| {
+            kmeans_refine(pixels, seed, iterations.max(0) as _, threads.max(0) as _)
+        });
+
+        palette_data(&palette)
+    })
+}
+// endregion
+
+// region GIF Ditherer
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_native(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+            atkinson_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_image(_env, image_ptr, |pixels, width, height| {
+            atkinson_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_pixmap(_env, pixmap_ptr, |pixels, width, height| {
+            atkinson_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024JJN_native(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+            jjn_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024JJN_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_image(_env, image_ptr, |pixels, width, height| {
+            jjn_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024JJN_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_pixmap(_env, pixmap_ptr, |pixels, width, height| {
+            jjn_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_native(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+            sierra_lite_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_image(_env, image_ptr, |pixels, width, height| {
+            sierra_lite_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_pixmap(_env, pixmap_ptr, |pixels, width, height| {
+            sierra_lite_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Stucki_native(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+            stucki_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Stucki_nativeImage(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_image(_env, image_ptr, |pixels, width, height| {
+            stucki_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Stucki_nativePixmap(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, palette_ptr: jlong,
+) -> jlong {
+    with_palette(_env, palette_ptr, |palette| {
+        let colors = read_pixmap(_env, pixmap_ptr, |pixels, width, height| {
+            stucki_ditherer(pixels, width, height, palette)
+        });
+
+        dithered_data(&colors)
+    })
+}
+// endregion
+
+// region GIF Palette
+
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
+pub extern "system" fn Java_xyz_cssxsh_gif_Palette_remap(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
 ) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let mut vec: Vec<[u8; 4]>;
-    let pixels = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            vec = Vec::with_capacity(capacity);
-            vec.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                vec.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            vec.as_slice()
-        }
-    };
+    with_palette(_env, palette_ptr, |palette| {
+        let indexed = read_bitmap(_env, bitmap_ptr, |pixels, _, _| remap(pixels, palette));
 
-    let palette = octtree_quantizer(pixels, count as _, sort == JNI_TRUE);
-    let bytes = unsafe { slice::from_raw_parts(palette.as_ptr() as _, palette.len() * 3) };
-    let data = Data::new_copy(bytes);
+        Data::new_copy(indexed.as_slice()).unwrap() as _
+    })
+}
 
-    sk_bitmap.unwrap();
-    data.unwrap() as _
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Palette_histogram(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, top: jint,
+) -> jintArray {
+    let (unique, entries) = read_bitmap(_env, bitmap_ptr, |pixels, _, _| histogram(pixels, top.max(0) as _));
+
+    entries_array(_env, vec![unique as jint], entries)
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
-) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let mut vec: Vec<[u8; 4]>;
-    let pixels = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            vec = Vec::with_capacity(capacity);
-            vec.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                vec.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            vec.as_slice()
-        }
-    };
+pub extern "system" fn Java_xyz_cssxsh_gif_Palette_error(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+) -> jdoubleArray {
+    with_palette(_env, palette_ptr, |palette| {
+        let (mse, max, psnr) = read_bitmap(_env, bitmap_ptr, |pixels, _, _| quantization_error(pixels, palette));
+        let arr = _env.new_double_array(3)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
 
-    let palette = mediancut_quantizer(pixels, count as _, sort == JNI_TRUE);
-    let bytes = unsafe { slice::from_raw_parts(palette.as_ptr() as _, palette.len() * 3) };
-    let data = Data::new_copy(bytes);
+        _env.set_double_array_region(arr, 0, &[mse, max, psnr])
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
 
-    sk_bitmap.unwrap();
-    data.unwrap() as _
+        arr
+    })
 }
 
+// endregion
+
+// region GIF Encoder
+
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, count: jint, sort: jboolean,
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_file(
+    _env: JNIEnv, _this: jclass, path: JString, width: jint, height: jint, palette: jlong,
 ) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let mut vec: Vec<[u8; 4]>;
-    let pixels = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            vec = Vec::with_capacity(capacity);
-            vec.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                vec.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            vec.as_slice()
+    if !check_size(_env, width, height) {
+        return 0;
+    }
+    let str = _env.get_string(path)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let text = str.to_str()
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let file = match File::create(text) {
+        Ok(file) => file,
+        Err(error) => return throw(_env, IO_EXCEPTION, format!("{}: {}", text, error)),
+    };
+    let Some(global_palette) = Data::wrap(palette as _) else {
+        return throw(_env, NULL_POINTER, "palette is null");
+    };
+    let writer = Writer::new(file, width as _, height as _, global_palette.as_bytes());
+
+    global_palette.unwrap();
+    match writer {
+        Ok(writer) => into_handle(writer),
+        Err(error) => encoding_error(_env, error)
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_setRepeat(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, value: jint,
+) {
+    let repeat = if (0..65535).contains(&value) {
+        Repeat::Finite(value as _)
+    } else {
+        Repeat::Infinite
+    };
+
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        if let Err(error) = writer.encoder.set_repeat(repeat) {
+            encoding_error(_env, error)
         }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_setResize(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, mode: jint, sampling: jint,
+) {
+    let Some(resize) = Resize::from_i32(mode) else {
+        return throw(_env, ILLEGAL_ARGUMENT, format!("unknown resize mode {}", mode));
+    };
+    let Some(sampling) = Sampling::from_i32(sampling) else {
+        return throw(_env, ILLEGAL_ARGUMENT, format!("unknown sampling {}", sampling));
     };
 
-    let palette = kmeans_quantizer(pixels, count as _, sort == JNI_TRUE);
-    let bytes = unsafe { slice::from_raw_parts(palette.as_ptr() as _, palette.len() * 3) };
-    let data = Data::new_copy(bytes);
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        writer.resize = resize;
+        writer.sampling = sampling;
+    })
+}
 
-    sk_bitmap.unwrap();
-    data.unwrap() as _
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_setInterlaced(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, value: jboolean,
+) {
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        writer.interlaced = value != 0;
+    })
 }
 
-// endregion
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeFrame(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, frame_ptr: jlong,
+) {
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+            if let Err(error) = writer.write_frame(frame) {
+                encoding_error(_env, error)
+            }
+        })
+    })
+}
 
-// region GIF Ditherer
+// the overloads without `lossy` keep Kotlin code compiled against the older signatures working
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
-) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let data = Data::wrap(palette_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let mut pixels: Vec<[u8; 4]>;
-    let colors: &[[u8; 4]] = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels.as_slice()
-        }
-    };
-    let palette = unsafe { slice::from_raw_parts(data.as_ptr() as _, data.len() / 3) };
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeImage__JJIII(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, image_ptr: jlong, delay: jint, dispose: jint, speed: jint,
+) {
+    Java_xyz_cssxsh_gif_Encoder_writeImage__JJIIII(_env, _this, encoder_ptr, image_ptr, delay, dispose, speed, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeImage__JJIIII(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, image_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
+) {
+    if !check_speed(_env, speed) {
+        return;
+    }
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
 
-    let temp = atkinson_ditherer(colors, bitmap.width(), bitmap.height(), palette);
-    let bytes = unsafe { slice::from_raw_parts(temp.as_ptr() as _, temp.len() * 3) };
-    let result = Data::new_copy(bytes);
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        let (mut pixels, rect) = canvas_image(_env, image_ptr, |width, height| writer.placement(width, height), writer.sampling);
+        let mut frame = rgba_frame(&mut pixels, rect, speed);
 
-    sk_bitmap.unwrap();
-    data.unwrap();
-    result.unwrap() as _
+        frame.delay = delay as _;
+        frame.dispose = dispose;
+
+        if let Err(error) = writer.write_lossy_frame(&frame, lossy.max(0) as _) {
+            encoding_error(_env, error)
+        }
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024JJN_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
-) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let data = Data::wrap(palette_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let mut pixels: Vec<[u8; 4]>;
-    let colors: &[[u8; 4]] = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeBitmap__JJIII(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, bitmap_ptr: jlong, delay: jint, dispose: jint, speed: jint,
+) {
+    Java_xyz_cssxsh_gif_Encoder_writeBitmap__JJIIII(_env, _this, encoder_ptr, bitmap_ptr, delay, dispose, speed, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeBitmap__JJIIII(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, bitmap_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
+) {
+    if !check_speed(_env, speed) {
+        return;
+    }
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
+
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        let (mut pixels, rect) = canvas_bitmap(_env, bitmap_ptr, |width, height| writer.placement(width, height), writer.sampling);
+        let mut frame = rgba_frame(&mut pixels, rect, speed);
+
+        frame.delay = delay as _;
+        frame.dispose = dispose;
+
+        if let Err(error) = writer.write_lossy_frame(&frame, lossy.max(0) as _) {
+            encoding_error(_env, error)
         }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels.as_slice()
+    })
+}
+
+fn java_string(_env: JNIEnv, string: JString) -> String {
+    let str = _env.get_string(string)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    str.to_str()
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()))
+        .to_owned()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeComment(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, text: JString,
+) {
+    let text = java_string(_env, text);
+
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        if let Err(error) = writer.write_comment(&text) {
+            encoding_error(_env, error.into())
         }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeApplication(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, identifier: JString, data_ptr: jlong,
+) {
+    let identifier: [u8; 11] = match java_string(_env, identifier).into_bytes().try_into() {
+        Ok(identifier) => identifier,
+        Err(_) => return throw(_env, ILLEGAL_ARGUMENT, "identifier needs to be 8 bytes of name and 3 bytes of authentication code"),
+    };
+    let Some(data) = Data::wrap(data_ptr as _) else {
+        return throw(_env, NULL_POINTER, "data is null");
     };
-    let palette = unsafe { slice::from_raw_parts(data.as_ptr() as _, data.len() / 3) };
 
-    let temp = jjn_ditherer(colors, bitmap.width(), bitmap.height(), palette);
-    let bytes = unsafe { slice::from_raw_parts(temp.as_ptr() as _, temp.len() * 3) };
-    let result = Data::new_copy(bytes);
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        if let Err(error) = writer.write_application(&identifier, data.as_bytes()) {
+            encoding_error(_env, error.into())
+        }
+    });
 
-    sk_bitmap.unwrap();
     data.unwrap();
-    result.unwrap() as _
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
-) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let data = Data::wrap(palette_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let mut pixels: Vec<[u8; 4]>;
-    let colors: &[[u8; 4]] = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels.as_slice()
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeXMP(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, xmp: JString,
+) {
+    let xmp = java_string(_env, xmp);
+
+    with_handle(_env, encoder_ptr, |writer: &mut Writer<File>| {
+        if let Err(error) = writer.write_xmp(&xmp) {
+            encoding_error(_env, error.into())
         }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_close(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong,
+) {
+    let _: Option<Writer<File>> = take_handle(_env, encoder_ptr);
+}
+
+// endregion
+
+// region GIF Decoder
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_file(
+    _env: JNIEnv, _this: jclass, path: JString,
+) -> jlong {
+    let str = _env.get_string(path)
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let text = str.to_str()
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    let bytes = match std::fs::read(text) {
+        Ok(bytes) => bytes,
+        Err(error) => return throw(_env, IO_EXCEPTION, format!("{}: {}", text, error)),
     };
-    let palette = unsafe { slice::from_raw_parts(data.as_ptr() as _, data.len() / 3) };
 
-    let temp = sierra_lite_ditherer(colors, bitmap.width(), bitmap.height(), palette);
-    let bytes = unsafe { slice::from_raw_parts(temp.as_ptr() as _, temp.len() * 3) };
-    let result = Data::new_copy(bytes);
+    match Reader::new(bytes) {
+        Ok(reader) => into_handle(reader),
+        Err(error) => decoding_error(_env, error)
+    }
+}
 
-    sk_bitmap.unwrap();
-    data.unwrap();
-    result.unwrap() as _
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getSize(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) -> jintArray {
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        let buf = [reader.decoder.width() as jint, reader.decoder.height() as jint];
+        let arr = _env.new_int_array(2)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+        _env.set_int_array_region(arr, 0, &buf)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+
+        arr
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Ditherer_00024Stucki_native(
-    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, palette_ptr: jlong,
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getGlobalPalette(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
 ) -> jlong {
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
-    let data = Data::wrap(palette_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let mut pixels: Vec<[u8; 4]>;
-    let colors: &[[u8; 4]] = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            bitmap.pixmap().pixels()
-                .unwrap_or_else(|| _env.fatal_error("get pixels fail."))
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        let data = match reader.decoder.global_palette() {
+            Some(palette) => Data::new_copy(palette),
+            None => Data::new_empty()
+        };
+
+        data.unwrap() as _
+    })
+}
+
+fn string_array<'a>(_env: JNIEnv, items: impl ExactSizeIterator<Item = &'a [u8]>) -> jobjectArray {
+    let arr = _env.new_object_array(items.len() as _, "java/lang/String", JObject::null())
+        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    for (index, item) in items.enumerate() {
+        let str = _env.new_string(String::from_utf8_lossy(item))
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+        _env.set_object_array_element(arr, index as _, str)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    }
+
+    arr
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getComments(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) -> jobjectArray {
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        let comments: Vec<&[u8]> = reader.comments().collect();
+
+        string_array(_env, comments.into_iter())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getXMP(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) -> jstring {
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        match reader.xmp() {
+            Some(xmp) => _env.new_string(String::from_utf8_lossy(xmp))
+                .unwrap_or_else(|error| _env.fatal_error(error.to_string()))
+                .into_raw(),
+            None => JObject::null().into_raw()
         }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size() / 4;
-            pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, [0; 4]);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels.as_slice()
+    })
+}
+
+/// Identifiers are the 8 byte application identifier followed by the 3 byte authentication code,
+/// e.g. `NETSCAPE2.0`.
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getApplicationIdentifiers(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) -> jobjectArray {
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        let identifiers: Vec<&[u8]> = reader.applications().map(|(identifier, _)| identifier).collect();
+
+        string_array(_env, identifiers.into_iter())
+    })
+}
+
+/// Returns the data of the first application extension with the identifier, or `0` when there is none.
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_getApplication(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong, identifier: JString,
+) -> jlong {
+    let identifier = java_string(_env, identifier);
+
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        let data = reader.applications()
+            .find(|(id, _)| *id == identifier.as_bytes())
+            .map(|(_, data)| Data::new_copy(data));
+
+        match data {
+            Some(data) => data.unwrap() as _,
+            None => 0
         }
-    };
-    let palette = unsafe { slice::from_raw_parts(data.as_ptr() as _, data.len() / 3) };
+    })
+}
 
-    let temp = stucki_ditherer(colors, bitmap.width(), bitmap.height(), palette);
-    let bytes = unsafe { slice::from_raw_parts(temp.as_ptr() as _, temp.len() * 3) };
-    let result = Data::new_copy(bytes);
+/// Returns the next frame with its rows in display order, or `0` after the last one.
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_readFrame(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) -> jlong {
+    with_handle(_env, decoder_ptr, |reader: &mut Reader| {
+        match reader.next_frame() {
+            Ok(Some(frame)) => into_handle(frame),
+            Ok(None) => 0,
+            Err(error) => decoding_error(_env, error)
+        }
+    })
+}
 
-    sk_bitmap.unwrap();
-    data.unwrap();
-    result.unwrap() as _
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Decoder_close(
+    _env: JNIEnv, _this: jclass, decoder_ptr: jlong,
+) {
+    let _: Option<Reader> = take_handle(_env, decoder_ptr);
 }
 
 // endregion
 
-// region GIF Encoder
+// region GIF Pipeline
+
+fn frame_job(canvas: (Vec<u8>, [u16; 4]), delay: jint, dispose: DisposalMethod, speed: jint, lossy: jint) -> Job {
+    let (pixels, [left, top, width, height]) = canvas;
+
+    Job {
+        pixels,
+        width,
+        height,
+        left,
+        top,
+        delay: delay as _,
+        dispose,
+        speed,
+        lossy: lossy.max(0) as _,
+    }
+}
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_file(
-    _env: JNIEnv, _this: jclass, path: JString, width: jint, height: jint, palette: jlong,
+pub extern "system" fn Java_xyz_cssxsh_gif_Pipeline_create(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, workers: jint, capacity: jint,
 ) -> jlong {
-    let str = _env.get_string(path)
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
-    let text = str.to_str()
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
-    let file = File::create(text)
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
-    let global_palette = Data::wrap(palette as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let encoder = Encoder::new(file, width as _, height as _, global_palette.as_bytes())
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    // the pipeline owns the encoder until finish hands it back
+    match take_handle::<Writer<File>>(_env, encoder_ptr) {
+        Some(writer) => into_handle(Pipeline::new(writer, workers.max(0) as _, capacity.max(0) as _)),
+        None => 0
+    }
+}
 
-    global_palette.unwrap();
-    Box::into_raw(Box::new(encoder)) as _
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Pipeline_writeImage(
+    _env: JNIEnv, _this: jclass, pipeline_ptr: jlong, image_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
+) {
+    if !check_speed(_env, speed) {
+        return;
+    }
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
+
+    with_handle(_env, pipeline_ptr, |pipeline: &mut Pipeline<File>| {
+        let canvas = canvas_image(_env, image_ptr, |width, height| pipeline.placement(width, height), pipeline.sampling);
+
+        if let Err(error) = pipeline.submit(frame_job(canvas, delay, dispose, speed, lossy)) {
+            throw(_env, ILLEGAL_STATE, error.to_string())
+        }
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_setRepeat(
-    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, value: jint,
+pub extern "system" fn Java_xyz_cssxsh_gif_Pipeline_writeBitmap(
+    _env: JNIEnv, _this: jclass, pipeline_ptr: jlong, bitmap_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
 ) {
-    let mut encoder: Box<Encoder<File>> = unsafe { Box::from_raw(encoder_ptr as _) };
-    let repeat = if (0..65535).contains(&value) {
-        Repeat::Finite(value as _)
-    } else {
-        Repeat::Infinite
+    if !check_speed(_env, speed) {
+        return;
+    }
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
+
+    with_handle(_env, pipeline_ptr, |pipeline: &mut Pipeline<File>| {
+        let canvas = canvas_bitmap(_env, bitmap_ptr, |width, height| pipeline.placement(width, height), pipeline.sampling);
+
+        if let Err(error) = pipeline.submit(frame_job(canvas, delay, dispose, speed, lossy)) {
+            throw(_env, ILLEGAL_STATE, error.to_string())
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Pipeline_finish(
+    _env: JNIEnv, _this: jclass, pipeline_ptr: jlong,
+) -> jlong {
+    let pipeline: Pipeline<File> = match take_handle(_env, pipeline_ptr) {
+        Some(pipeline) => pipeline,
+        None => return 0
     };
 
-    encoder.set_repeat(repeat)
+    match pipeline.finish() {
+        Ok(writer) => into_handle(writer),
+        Err(error) => throw(_env, ILLEGAL_STATE, error.to_string()),
+    }
+}
+
+/// Abandons a pipeline that will not be finished, its threads are joined and the file is closed.
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Pipeline_close(
+    _env: JNIEnv, _this: jclass, pipeline_ptr: jlong,
+) {
+    if let Some(pipeline) = take_handle::<Pipeline<File>>(_env, pipeline_ptr) {
+        pipeline.close();
+    }
+}
+
+// endregion
+
+// region GIF Encode Job
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_EncodeJob_create(
+    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, workers: jint, capacity: jint, listener: JObject,
+) -> jlong {
+    let listener = Listener::new(_env, listener)
         .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    // the job owns the encoder and closes it once finished
+    match take_handle::<Writer<File>>(_env, encoder_ptr) {
+        Some(writer) => into_shared_handle(EncodeJob::new(writer, workers.max(0) as _, capacity.max(0) as _, listener)),
+        None => 0
+    }
+}
 
-    Box::into_raw(encoder);
+fn with_job_pipeline(_env: JNIEnv, job_ptr: jlong, block: impl FnOnce(&mut Pipeline<File>)) {
+    with_shared_handle(_env, job_ptr, |job: &EncodeJob| {
+        lock(_env, job.pipeline(), "encode job", |pipeline| match pipeline {
+            Some(pipeline) => block(pipeline),
+            None => throw(_env, ILLEGAL_STATE, "encode job is already finished"),
+        })
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeFrame(
-    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, frame_ptr: jlong,
+pub extern "system" fn Java_xyz_cssxsh_gif_EncodeJob_writeImage(
+    _env: JNIEnv, _this: jclass, job_ptr: jlong, image_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
 ) {
-    let mut encoder: Box<Encoder<File>> = unsafe { Box::from_raw(encoder_ptr as _) };
-    let frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
+    if !check_speed(_env, speed) {
+        return;
+    }
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
 
-    encoder.write_frame(frame.as_ref())
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    with_job_pipeline(_env, job_ptr, |pipeline| {
+        let canvas = canvas_image(_env, image_ptr, |width, height| pipeline.placement(width, height), pipeline.sampling);
 
-    Box::into_raw(frame);
-    Box::into_raw(encoder);
+        if let Err(error) = pipeline.submit(frame_job(canvas, delay, dispose, speed, lossy)) {
+            throw(_env, ILLEGAL_STATE, error.to_string())
+        }
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeImage(
-    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, image_ptr: jlong, delay: jint, dispose: jint, speed: jint,
+pub extern "system" fn Java_xyz_cssxsh_gif_EncodeJob_writeBitmap(
+    _env: JNIEnv, _this: jclass, job_ptr: jlong, bitmap_ptr: jlong, delay: jint, dispose: jint, speed: jint, lossy: jint,
 ) {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
+    if !check_speed(_env, speed) {
+        return;
     }
-    let image = Image::wrap(image_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap image fail."));
-    let mut encoder: Box<Encoder<File>> = unsafe { Box::from_raw(encoder_ptr as _) };
-
-    let mut pixels = match image.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            let pixmap = image.peek_pixels()
-                .unwrap_or_else(|| _env.fatal_error("peek pixels fail."));
-            let bytes = pixmap.bytes()
-                .unwrap_or_else(|| _env.fatal_error("get pixels bytes fail."));
-            bytes.to_vec()
-        }
-        _ => {
-            let image_info = image.image_info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size();
-            let mut pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, 0);
-
-            image.read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-                CachingHint::Allow,
-            );
-
-            pixels
-        }
-    };
-    let mut frame = Frame::from_rgba_speed(
-        image.width() as _,
-        image.height() as _,
-        pixels.as_mut_slice(),
-        speed,
-    );
+    let Some(dispose) = dispose_method(_env, dispose) else { return };
 
-    frame.delay = delay as _;
-    frame.dispose = DisposalMethod::from_u8(dispose as _)
-        .unwrap_or_else(|| _env.fatal_error("get dispose method fail"));
+    with_job_pipeline(_env, job_ptr, |pipeline| {
+        let canvas = canvas_bitmap(_env, bitmap_ptr, |width, height| pipeline.placement(width, height), pipeline.sampling);
 
-    encoder.write_frame(&frame)
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+        if let Err(error) = pipeline.submit(frame_job(canvas, delay, dispose, speed, lossy)) {
+            throw(_env, ILLEGAL_STATE, error.to_string())
+        }
+    })
+}
 
-    image.unwrap();
-    Box::into_raw(encoder);
+/// Safe from any thread, also while another one is blocked writing a frame.
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_EncodeJob_cancel(
+    _env: JNIEnv, _this: jclass, job_ptr: jlong,
+) {
+    with_shared_handle(_env, job_ptr, |job: &EncodeJob| job.cancel())
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_writeBitmap(
-    _env: JNIEnv, _this: jclass, encoder_ptr: jlong, bitmap_ptr: jlong, delay: jint, dispose: jint, speed: jint,
+pub extern "system" fn Java_xyz_cssxsh_gif_EncodeJob_finish(
+    _env: JNIEnv, _this: jclass, job_ptr: jlong,
 ) {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
+    if let Some(job) = take_shared_handle::<EncodeJob>(_env, job_ptr) {
+        job.finish();
     }
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
+}
 
-    let mut encoder: Box<Encoder<File>> = unsafe { Box::from_raw(encoder_ptr as _) };
-    let mut pixels = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            let bytes = bitmap.pixmap().bytes()
-                .unwrap_or_else(|| _env.fatal_error("get pixels bytes fail."));
-            bytes.to_vec()
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size();
-            let mut pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, 0);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels
-        }
+// endregion
+
+// region GIF Budget
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Budget_create(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, limit: jlong, repeat: jint,
+) -> jlong {
+    if !check_size(_env, width, height) {
+        return 0;
+    }
+    let repeat = if (0..65535).contains(&repeat) {
+        Repeat::Finite(repeat as _)
+    } else {
+        Repeat::Infinite
     };
-    let mut frame = Frame::from_rgba_speed(
-        bitmap.width() as _,
-        bitmap.height() as _,
-        pixels.as_mut_slice(),
-        speed,
-    );
+    let budget = Budget::new(width as _, height as _, limit.max(0) as _, repeat, resample_rgba);
+
+    into_handle(budget)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Budget_addImage(
+    _env: JNIEnv, _this: jclass, budget_ptr: jlong, image_ptr: jlong, delay: jint,
+) {
+    with_handle(_env, budget_ptr, |budget: &mut Budget| {
+        read_image(_env, image_ptr, |pixels, width, height| {
+            budget.push(pixels, width as _, height as _, delay as _)
+        })
+    })
+}
 
-    frame.delay = delay as _;
-    frame.dispose = DisposalMethod::from_u8(dispose as _)
-        .unwrap_or_else(|| _env.fatal_error("get dispose method fail"));
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Budget_addBitmap(
+    _env: JNIEnv, _this: jclass, budget_ptr: jlong, bitmap_ptr: jlong, delay: jint,
+) {
+    with_handle(_env, budget_ptr, |budget: &mut Budget| {
+        read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+            budget.push(pixels, width as _, height as _, delay as _)
+        })
+    })
+}
 
-    encoder.write_frame(&frame)
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Budget_encode(
+    _env: JNIEnv, _this: jclass, budget_ptr: jlong, settings: jintArray,
+) -> jlong {
+    if settings.is_null() {
+        return throw(_env, NULL_POINTER, "settings is null");
+    }
+    // colors, lossy threshold, scale in percent, frame step, output size
+    let length = _env.get_array_length(settings)
         .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+    if length < 5 {
+        return throw(_env, ILLEGAL_ARGUMENT, format!("settings needs room for 5 values, got {}", length));
+    }
 
-    sk_bitmap.unwrap();
-    Box::into_raw(encoder);
+    with_handle(_env, budget_ptr, |budget: &mut Budget| {
+        let (chosen, output) = match budget.fit() {
+            Ok(fit) => fit,
+            Err(error) => return encoding_error(_env, error),
+        };
+        let buf = [
+            chosen.colors as jint,
+            chosen.lossy as jint,
+            (chosen.scale * 100.0).round() as jint,
+            chosen.step as jint,
+            output.len() as jint,
+        ];
+        _env.set_int_array_region(settings, 0, &buf)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+
+        Data::new_copy(output.as_slice()).unwrap() as _
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_close(
-    _env: JNIEnv, _this: jclass, encoder_ptr: jlong,
+pub extern "system" fn Java_xyz_cssxsh_gif_Budget_close(
+    _env: JNIEnv, _this: jclass, budget_ptr: jlong,
 ) {
-    let _: Box<Encoder<File>> = unsafe { Box::from_raw(encoder_ptr as _) };
+    let _: Option<Budget> = take_handle(_env, budget_ptr);
 }
 
 // endregion
@@ -488,9 +1482,74 @@ pub extern "system" fn Java_xyz_cssxsh_gif_Encoder_close(
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_default_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass,
 ) -> jlong {
-    let frame = Frame::default();
+    let frame: Frame<'static> = Frame::default();
+
+    into_handle(frame)
+}
+
+fn transparent_index(value: jint) -> Option<u8> {
+    if (0..=255).contains(&value) {
+        Some(value as u8)
+    } else {
+        None
+    }
+}
+
+fn check_palette(_env: JNIEnv, palette: &[u8]) -> bool {
+    if palette.len() % 3 != 0 || palette.len() > 256 * 3 {
+        return throw(_env, ILLEGAL_ARGUMENT, format!("palette of {} bytes is not a multiple of 3 of at most 768 bytes", palette.len()));
+    }
+
+    true
+}
+
+fn indexed_pixels_frame(_env: JNIEnv, width: jint, height: jint, pixels: &[u8], transparent: jint) -> jlong {
+    if !check_pixels(_env, width, height, 1, pixels.len()) {
+        return 0;
+    }
+
+    into_handle(Frame::from_indexed_pixels(width as _, height as _, pixels, transparent_index(transparent)))
+}
+
+fn palette_pixels_frame(_env: JNIEnv, width: jint, height: jint, pixels: &[u8], palette: &[u8], transparent: jint, lossy: jint) -> jlong {
+    if !check_pixels(_env, width, height, 1, pixels.len()) || !check_palette(_env, palette) {
+        return 0;
+    }
+
+    let mut frame = Frame::from_palette_pixels(width as _, height as _, pixels, palette, transparent_index(transparent));
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
+
+    into_handle(frame)
+}
+
+fn rgb_speed_frame(_env: JNIEnv, width: jint, height: jint, pixels: &[u8], speed: jint, lossy: jint) -> jlong {
+    if !check_speed(_env, speed) {
+        return 0;
+    }
+    if !check_pixels(_env, width, height, 3, pixels.len()) {
+        return 0;
+    }
+
+    let mut frame = Frame::from_rgb_speed(width as _, height as _, pixels, speed as _);
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
+
+    into_handle(frame)
+}
+
+fn rgba_speed_frame(_env: JNIEnv, width: jint, height: jint, pixels: &[u8], speed: jint, lossy: jint) -> jlong {
+    if !check_speed(_env, speed) {
+        return 0;
+    }
+    if !check_pixels(_env, width, height, 4, pixels.len()) {
+        return 0;
+    }
+
+    // from_rgba_speed rewrites the pixels while quantizing, so the Java array or buffer is copied
+    // first instead of being changed under its owner, the frame keeps only its indexed copy
+    let mut frame = Frame::from_rgba_speed(width as _, height as _, pixels.to_vec().as_mut_slice(), speed as _);
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
 
-    Box::into_raw(Box::from(frame)) as _
+    into_handle(frame)
 }
 
 #[no_mangle]
@@ -499,239 +1558,232 @@ pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromIndexedPixels_00024mirai_1s
 ) -> jlong {
     let pixels = Data::wrap(pixels as _)
         .unwrap_or_else(|| _env.fatal_error("wrap pixels fail."));
-    let transparent = if (0..=255).contains(&transparent) {
-        Some(transparent as u8)
-    } else {
-        None
-    };
 
-    let frame = Frame::from_indexed_pixels(width as _, height as _, pixels.as_bytes(), transparent);
+    let frame_ptr = indexed_pixels_frame(_env, width, height, pixels.as_bytes(), transparent);
 
     pixels.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    frame_ptr
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromIndexedPixelsArray_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jbyteArray, transparent: jint,
+) -> jlong {
+    with_byte_array(_env, pixels, |pixels| indexed_pixels_frame(_env, width, height, pixels, transparent))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromIndexedPixelsBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: JByteBuffer, transparent: jint,
+) -> jlong {
+    with_direct_buffer(_env, pixels, |pixels| indexed_pixels_frame(_env, width, height, pixels, transparent))
 }
 
+// as with the encoder writes, the frame constructors keep their overloads without `lossy`
+
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin__IIJJI(
     _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, palette: jlong, transparent: jint,
+) -> jlong {
+    Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin__IIJJII(_env, _this, width, height, pixels, palette, transparent, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin__IIJJII(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, palette: jlong, transparent: jint, lossy: jint,
 ) -> jlong {
     let pixels = Data::wrap(pixels as _)
         .unwrap_or_else(|| _env.fatal_error("wrap pixels fail."));
     let palette = Data::wrap(palette as _)
         .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
-    let transparent = if (0..=255).contains(&transparent) {
-        Some(transparent as u8)
-    } else {
-        None
-    };
 
-    let frame = Frame::from_palette_pixels(width as _, height as _, pixels.as_bytes(), palette.as_bytes(), transparent);
+    let frame_ptr = palette_pixels_frame(_env, width, height, pixels.as_bytes(), palette.as_bytes(), transparent, lossy);
 
     pixels.unwrap();
     palette.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    frame_ptr
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPalettePixelsArray_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jbyteArray, palette: jbyteArray, transparent: jint, lossy: jint,
+) -> jlong {
+    with_byte_array(_env, pixels, |pixels| {
+        with_byte_array(_env, palette, |palette| {
+            palette_pixels_frame(_env, width, height, pixels, palette, transparent, lossy)
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPalettePixelsBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: JByteBuffer, palette: JByteBuffer, transparent: jint, lossy: jint,
+) -> jlong {
+    with_direct_buffer(_env, pixels, |pixels| {
+        with_direct_buffer(_env, palette, |palette| {
+            palette_pixels_frame(_env, width, height, pixels, palette, transparent, lossy)
+        })
+    })
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin__IIJI(
     _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, speed: jint,
 ) -> jlong {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
-    }
+    Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin__IIJII(_env, _this, width, height, pixels, speed, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin__IIJII(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, speed: jint, lossy: jint,
+) -> jlong {
     let pixels = Data::wrap(pixels as _)
         .unwrap_or_else(|| _env.fatal_error("wrap pixels fail."));
 
-    let frame = Frame::from_rgb_speed(width as _, height as _, pixels.as_bytes(), speed as _);
+    let frame_ptr = rgb_speed_frame(_env, width, height, pixels.as_bytes(), speed, lossy);
 
     pixels.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    frame_ptr
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBSpeedArray_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jbyteArray, speed: jint, lossy: jint,
+) -> jlong {
+    with_byte_array(_env, pixels, |pixels| rgb_speed_frame(_env, width, height, pixels, speed, lossy))
 }
 
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBSpeedBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: JByteBuffer, speed: jint, lossy: jint,
+) -> jlong {
+    with_direct_buffer(_env, pixels, |pixels| rgb_speed_frame(_env, width, height, pixels, speed, lossy))
+}
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin__IIJI(
     _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, speed: jint,
 ) -> jlong {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
-    }
+    Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin__IIJII(_env, _this, width, height, pixels, speed, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin__IIJII(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jlong, speed: jint, lossy: jint,
+) -> jlong {
     let pixels = Data::wrap(pixels as _)
         .unwrap_or_else(|| _env.fatal_error("wrap pixels fail."));
 
-    let frame = Frame::from_rgba_speed(width as _, height as _, pixels.to_vec().as_mut_slice(), speed as _);
+    let frame_ptr = rgba_speed_frame(_env, width, height, pixels.as_bytes(), speed, lossy);
 
     pixels.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    frame_ptr
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBASpeedArray_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: jbyteArray, speed: jint, lossy: jint,
+) -> jlong {
+    with_byte_array(_env, pixels, |pixels| rgba_speed_frame(_env, width, height, pixels, speed, lossy))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromRGBASpeedBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, width: jint, height: jint, pixels: JByteBuffer, speed: jint, lossy: jint,
+) -> jlong {
+    with_direct_buffer(_env, pixels, |pixels| rgba_speed_frame(_env, width, height, pixels, speed, lossy))
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin__JI(
     _env: JNIEnv, _this: jclass, image_ptr: jlong, speed: jint,
 ) -> jlong {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
-    }
-    let image = Image::wrap(image_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap image fail."));
+    Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin__JII(_env, _this, image_ptr, speed, 0)
+}
 
-    let mut pixels = match image.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            let pixmap = image.peek_pixels()
-                .unwrap_or_else(|| _env.fatal_error("peek pixels fail."));
-            let bytes = pixmap.bytes()
-                .unwrap_or_else(|| _env.fatal_error("get pixels bytes fail."));
-            bytes.to_vec()
-        }
-        _ => {
-            let image_info = image.image_info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size();
-            let mut pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, 0);
-
-            image.read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-                CachingHint::Allow,
-            );
-
-            pixels
-        }
-    };
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin__JII(
+    _env: JNIEnv, _this: jclass, image_ptr: jlong, speed: jint, lossy: jint,
+) -> jlong {
+    if !check_speed(_env, speed) {
+        return 0;
+    }
 
-    let frame = Frame::from_rgba_speed(
-        image.width() as _,
-        image.height() as _,
-        pixels.as_mut_slice(),
-        speed,
-    );
+    let mut frame = read_image(_env, image_ptr, |pixels, width, height| {
+        Frame::from_rgba_speed(width as _, height as _, pixels.concat().as_mut_slice(), speed)
+    });
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
 
-    image.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    into_handle(frame)
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin__JI(
     _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, speed: jint,
 ) -> jlong {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
-    }
-    let sk_bitmap = RefHandle::wrap(bitmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkBitmap"));
-    let bitmap = Bitmap::wrap_ref(sk_bitmap.inner());
+    Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin__JII(_env, _this, bitmap_ptr, speed, 0)
+}
 
-    let mut pixels = match bitmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            let bytes = bitmap.pixmap().bytes()
-                .unwrap_or_else(|| _env.fatal_error("get pixels bytes fail."));
-            bytes.to_vec()
-        }
-        _ => {
-            let image_info = bitmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size();
-            let mut pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, 0);
-
-            bitmap.pixmap().read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels
-        }
-    };
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin__JII(
+    _env: JNIEnv, _this: jclass, bitmap_ptr: jlong, speed: jint, lossy: jint,
+) -> jlong {
+    if !check_speed(_env, speed) {
+        return 0;
+    }
 
-    let frame = Frame::from_rgba_speed(
-        bitmap.width() as _,
-        bitmap.height() as _,
-        pixels.as_mut_slice(),
-        speed,
-    );
+    let mut frame = read_bitmap(_env, bitmap_ptr, |pixels, width, height| {
+        Frame::from_rgba_speed(width as _, height as _, pixels.concat().as_mut_slice(), speed)
+    });
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
 
-    sk_bitmap.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    into_handle(frame)
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin__JI(
     _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, speed: jint,
 ) -> jlong {
-    if !(1..=30).contains(&speed) {
-        _env.fatal_error("speed needs to be in the range [1, 30]")
-    }
-    let sk_pixmap = RefHandle::wrap(pixmap_ptr as _)
-        .unwrap_or_else(|| _env.fatal_error("wrap SkPixmap"));
-    let pixmap = Pixmap::wrap_ref(sk_pixmap.inner());
+    Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin__JII(_env, _this, pixmap_ptr, speed, 0)
+}
 
-    let mut pixels = match pixmap.color_type() {
-        ColorType::RGBA8888 | ColorType::RGB888x => {
-            let bytes = pixmap.bytes()
-                .unwrap_or_else(|| _env.fatal_error("get pixels bytes fail."));
-            bytes.to_vec()
-        }
-        _ => {
-            let image_info = pixmap.info()
-                .with_color_type(ColorType::RGBA8888);
-            let capacity = image_info.compute_min_byte_size();
-            let mut pixels = Vec::with_capacity(capacity);
-            pixels.resize(capacity, 0);
-
-            pixmap.read_pixels(
-                &image_info,
-                pixels.as_mut_slice(),
-                image_info.min_row_bytes(),
-                IPoint { x: 0, y: 0 },
-            );
-
-            pixels
-        }
-    };
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin__JII(
+    _env: JNIEnv, _this: jclass, pixmap_ptr: jlong, speed: jint, lossy: jint,
+) -> jlong {
+    if !check_speed(_env, speed) {
+        return 0;
+    }
 
-    let frame = Frame::from_rgba_speed(
-        pixmap.width() as _,
-        pixmap.height() as _,
-        pixels.as_mut_slice(),
-        speed,
-    );
+    let mut frame = read_pixmap(_env, pixmap_ptr, |pixels, width, height| {
+        Frame::from_rgba_speed(width as _, height as _, pixels.concat().as_mut_slice(), speed)
+    });
+    lossy_frame(&mut frame, None, lossy.max(0) as _);
 
-    sk_pixmap.unwrap();
-    Box::into_raw(Box::from(frame)) as _
+    into_handle(frame)
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_close_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong,
 ) {
-    let _: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
+    let _: Option<Frame<'static>> = take_handle(_env, frame_ptr);
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getDelay_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong,
 ) -> jint {
-    let frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    let value = frame.delay;
-
-    Box::into_raw(frame);
-
-    value as _
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| frame.delay as _)
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setDelay_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong, value: jint,
 ) {
-    let mut frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    frame.delay = value as _;
-
-    Box::into_raw(frame);
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.delay = value as _;
+    })
 }
 
 
@@ -739,74 +1791,207 @@ pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setDelay_00024mirai_1skia_1plug
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getDispose_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong,
 ) -> jint {
-    let frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    let value = frame.dispose;
-
-    Box::into_raw(frame);
-
-    value as _
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| frame.dispose as _)
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setDispose_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong, value: jint,
 ) {
-    let mut frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    frame.dispose = DisposalMethod::from_u8(value as _)
-        .unwrap_or_else(|| _env.fatal_error("get dispose method fail"));
+    let Some(dispose) = dispose_method(_env, value) else { return };
 
-    Box::into_raw(frame);
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.dispose = dispose;
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getRect_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong,
 ) -> jintArray {
-    let frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    let arr = _env.new_int_array(4)
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
-    let buf = [
-        frame.top as jint,
-        frame.left as jint,
-        frame.width as jint,
-        frame.height as jint
-    ];
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        let arr = _env.new_int_array(4)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+        let buf = [
+            frame.top as jint,
+            frame.left as jint,
+            frame.width as jint,
+            frame.height as jint
+        ];
+
+        _env.set_int_array_region(arr, 0, &buf)
+            .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+
+        arr
+    })
+}
 
-    _env.set_int_array_region(arr, 0, &buf)
-        .unwrap_or_else(|error| _env.fatal_error(error.to_string()));
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setRect_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, top: jint, left: jint, width: jint, height: jint,
+) {
+    if [top, left, width, height].iter().any(|value| !(0..=u16::MAX as jint).contains(value)) {
+        return throw(_env, ILLEGAL_ARGUMENT, "rect values need to be in the range [0, 65535]");
+    }
 
-    Box::into_raw(frame);
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        // the buffer is not resized, so the rect has to keep covering exactly its pixels
+        if frame.buffer.len() != width as usize * height as usize {
+            return throw(_env, ILLEGAL_ARGUMENT, format!("rect of {}x{} does not match the {} pixel buffer", width, height, frame.buffer.len()));
+        }
+        frame.top = top as _;
+        frame.left = left as _;
+        frame.width = width as _;
+        frame.height = height as _;
+    })
+}
 
-    arr
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_copy_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong,
+) -> jlong {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| into_handle(frame.clone()))
 }
 
 #[no_mangle]
-pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setRect_00024mirai_1skia_1plugin(
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_crop_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong, top: jint, left: jint, width: jint, height: jint,
 ) {
-    let mut frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    frame.top = top as _;
-    frame.left = left as _;
-    frame.width = width as _;
-    frame.height = height as _;
+    let rect = [left, top, width, height].map(|value| value.clamp(0, u16::MAX as _) as u16);
+
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        if frame.buffer.len() != frame.width as usize * frame.height as usize {
+            return throw(_env, ILLEGAL_STATE, "frame buffer does not match its rect");
+        }
+        if !frame::crop(frame, rect) {
+            throw(_env, ILLEGAL_ARGUMENT, "crop rect does not overlap the frame")
+        }
+    })
+}
 
-    Box::into_raw(frame);
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_offset_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, dx: jint, dy: jint,
+) {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        if !frame::offset(frame, dx, dy) {
+            throw(_env, ILLEGAL_ARGUMENT, "offset moves the frame out of the logical screen range")
+        }
+    })
 }
 
 #[no_mangle]
 pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getPalette_00024mirai_1skia_1plugin(
     _env: JNIEnv, _this: jclass, frame_ptr: jlong,
 ) -> jlong {
-    let frame: Box<Frame> = unsafe { Box::from_raw(frame_ptr as _) };
-    let data = if let Some(vec) = &frame.palette {
-        Data::new_copy(vec.as_slice())
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        let data = if let Some(vec) = &frame.palette {
+            Data::new_copy(vec.as_slice())
+        } else {
+            Data::new_empty()
+        };
+
+        data.unwrap() as _
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setPalette_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, palette: jlong,
+) {
+    // a null palette falls back to the global one
+    let palette = if palette == 0 {
+        None
     } else {
-        Data::new_empty()
+        let data = Data::wrap(palette as _)
+            .unwrap_or_else(|| _env.fatal_error("wrap palette fail."));
+        let vec = data.as_bytes().to_vec();
+
+        data.unwrap();
+        if !check_palette(_env, &vec) {
+            return;
+        }
+        Some(vec)
     };
 
-    Box::into_raw(frame);
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.palette = palette;
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getTransparent_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong,
+) -> jint {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.transparent.map_or(-1, |index| index as _)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setTransparent_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, value: jint,
+) {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.transparent = transparent_index(value);
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getInterlaced_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong,
+) -> jboolean {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| frame.interlaced as _)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setInterlaced_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, value: jboolean,
+) {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.interlaced = value != 0;
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getNeedsUserInput_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong,
+) -> jboolean {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| frame.needs_user_input as _)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setNeedsUserInput_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, value: jboolean,
+) {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        frame.needs_user_input = value != 0;
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_getBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong,
+) -> jlong {
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        Data::new_copy(frame.buffer.as_ref()).unwrap() as _
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_xyz_cssxsh_gif_Frame_setBuffer_00024mirai_1skia_1plugin(
+    _env: JNIEnv, _this: jclass, frame_ptr: jlong, buffer: jlong,
+) {
+    let data = Data::wrap(buffer as _)
+        .unwrap_or_else(|| _env.fatal_error("wrap buffer fail."));
+
+    with_handle(_env, frame_ptr, |frame: &mut Frame<'static>| {
+        if check_pixels(_env, frame.width as _, frame.height as _, 1, data.len()) {
+            frame.buffer = Cow::Owned(data.as_bytes().to_vec());
+        }
+    });
 
-    data.unwrap() as _
+    data.unwrap();
 }
 
 // endregion
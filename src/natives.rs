@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use jni::{JavaVM, JNIEnv, NativeMethod};
+use jni::objects::*;
+use jni::sys::*;
+use crate::*;
+
+const GIF_PACKAGE: &str = "xyz/cssxsh/gif";
+
+const SKIA_PACKAGE: &str = "xyz/cssxsh/skia";
+
+/// Kotlin appends the module name to `internal` members, `getDelay` compiles to `getDelay$mirai_skia_plugin`.
+const MODULE: &str = "mirai_skia_plugin";
+
+struct Native {
+    class: &'static str,
+    name: &'static str,
+    internal: bool,
+    sig: &'static str,
+    fn_ptr: *mut c_void,
+}
+
+macro_rules! native {
+    ($class:literal, $name:literal, internal, $sig:literal, $function:ident) => {
+        Native { class: $class, name: $name, internal: true, sig: $sig, fn_ptr: $function as *mut c_void }
+    };
+    ($class:literal, $name:literal, $sig:literal, $function:ident) => {
+        Native { class: $class, name: $name, internal: false, sig: $sig, fn_ptr: $function as *mut c_void }
+    };
+}
+
+fn natives() -> Vec<Native> {
+    vec![
+        native!("xyz/cssxsh/gif/Library", "info", "()Ljava/lang/String;", Java_xyz_cssxsh_gif_Library_info),
+
+        native!("xyz/cssxsh/gif/Source", "getConvertSRGB", "()Z", Java_xyz_cssxsh_gif_Source_getConvertSRGB),
+        native!("xyz/cssxsh/gif/Source", "setConvertSRGB", "(Z)V", Java_xyz_cssxsh_gif_Source_setConvertSRGB),
+
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "native", "(JIZ)J", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JIZ),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "native", "(JII)J", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JII),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "native", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_native__JIII),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "nativeImage", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_nativeImage),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "nativePixmap", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_nativePixmap),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "populations", "(JII)[I", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populations__JII),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "populations", "(JIII)[I", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populations__JIII),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "populationsImage", "(JIII)[I", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populationsImage),
+        native!("xyz/cssxsh/gif/Quantizer$OctTree", "populationsPixmap", "(JIII)[I", Java_xyz_cssxsh_gif_Quantizer_00024OctTree_populationsPixmap),
+
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "native", "(JIZ)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIZ),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "native", "(JII)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JII),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "native", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIII),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "native", "(JIIIZZ)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_native__JIIIZZ),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "nativeImage", "(JIIIZZ)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_nativeImage),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "nativePixmap", "(JIIIZZ)J", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_nativePixmap),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "populations", "(JII)[I", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JII),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "populations", "(JIII)[I", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JIII),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "populations", "(JIIIZZ)[I", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populations__JIIIZZ),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "populationsImage", "(JIIIZZ)[I", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populationsImage),
+        native!("xyz/cssxsh/gif/Quantizer$MedianCut", "populationsPixmap", "(JIIIZZ)[I", Java_xyz_cssxsh_gif_Quantizer_00024MedianCut_populationsPixmap),
+
+        native!("xyz/cssxsh/gif/Quantizer$KMeans", "native", "(JIZ)J", Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JIZ),
+        native!("xyz/cssxsh/gif/Quantizer$KMeans", "native", "(JII)J", Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JII),
+        native!("xyz/cssxsh/gif/Quantizer$KMeans", "native", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024KMeans_native__JIII),
+        native!("xyz/cssxsh/gif/Quantizer$KMeans", "nativeImage", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024KMeans_nativeImage),
+        native!("xyz/cssxsh/gif/Quantizer$KMeans", "nativePixmap", "(JIII)J", Java_xyz_cssxsh_gif_Quantizer_00024KMeans_nativePixmap),
+
+        native!("xyz/cssxsh/gif/Quantizer", "refine", "(JJII)J", Java_xyz_cssxsh_gif_Quantizer_refine),
+        native!("xyz/cssxsh/gif/Quantizer", "refineImage", "(JJII)J", Java_xyz_cssxsh_gif_Quantizer_refineImage),
+        native!("xyz/cssxsh/gif/Quantizer", "refinePixmap", "(JJII)J", Java_xyz_cssxsh_gif_Quantizer_refinePixmap),
+
+        native!("xyz/cssxsh/gif/Ditherer$Atkinson", "native", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_native),
+        native!("xyz/cssxsh/gif/Ditherer$Atkinson", "nativeImage", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_nativeImage),
+        native!("xyz/cssxsh/gif/Ditherer$Atkinson", "nativePixmap", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Atkinson_nativePixmap),
+
+        native!("xyz/cssxsh/gif/Ditherer$JJN", "native", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024JJN_native),
+        native!("xyz/cssxsh/gif/Ditherer$JJN", "nativeImage", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024JJN_nativeImage),
+        native!("xyz/cssxsh/gif/Ditherer$JJN", "nativePixmap", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024JJN_nativePixmap),
+
+        native!("xyz/cssxsh/gif/Ditherer$SierraLite", "native", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_native),
+        native!("xyz/cssxsh/gif/Ditherer$SierraLite", "nativeImage", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_nativeImage),
+        native!("xyz/cssxsh/gif/Ditherer$SierraLite", "nativePixmap", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024SierraLite_nativePixmap),
+
+        native!("xyz/cssxsh/gif/Ditherer$Stucki", "native", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Stucki_native),
+        native!("xyz/cssxsh/gif/Ditherer$Stucki", "nativeImage", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Stucki_nativeImage),
+        native!("xyz/cssxsh/gif/Ditherer$Stucki", "nativePixmap", "(JJ)J", Java_xyz_cssxsh_gif_Ditherer_00024Stucki_nativePixmap),
+
+        native!("xyz/cssxsh/gif/Palette", "remap", "(JJ)J", Java_xyz_cssxsh_gif_Palette_remap),
+        native!("xyz/cssxsh/gif/Palette", "histogram", "(JI)[I", Java_xyz_cssxsh_gif_Palette_histogram),
+        native!("xyz/cssxsh/gif/Palette", "error", "(JJ)[D", Java_xyz_cssxsh_gif_Palette_error),
+
+        native!("xyz/cssxsh/gif/Encoder", "file", "(Ljava/lang/String;IIJ)J", Java_xyz_cssxsh_gif_Encoder_file),
+        native!("xyz/cssxsh/gif/Encoder", "setRepeat", "(JI)V", Java_xyz_cssxsh_gif_Encoder_setRepeat),
+        native!("xyz/cssxsh/gif/Encoder", "setResize", "(JII)V", Java_xyz_cssxsh_gif_Encoder_setResize),
+        native!("xyz/cssxsh/gif/Encoder", "setInterlaced", "(JZ)V", Java_xyz_cssxsh_gif_Encoder_setInterlaced),
+        native!("xyz/cssxsh/gif/Encoder", "writeFrame", "(JJ)V", Java_xyz_cssxsh_gif_Encoder_writeFrame),
+        native!("xyz/cssxsh/gif/Encoder", "writeImage", "(JJIII)V", Java_xyz_cssxsh_gif_Encoder_writeImage__JJIII),
+        native!("xyz/cssxsh/gif/Encoder", "writeImage", "(JJIIII)V", Java_xyz_cssxsh_gif_Encoder_writeImage__JJIIII),
+        native!("xyz/cssxsh/gif/Encoder", "writeBitmap", "(JJIII)V", Java_xyz_cssxsh_gif_Encoder_writeBitmap__JJIII),
+        native!("xyz/cssxsh/gif/Encoder", "writeBitmap", "(JJIIII)V", Java_xyz_cssxsh_gif_Encoder_writeBitmap__JJIIII),
+        native!("xyz/cssxsh/gif/Encoder", "writeComment", "(JLjava/lang/String;)V", Java_xyz_cssxsh_gif_Encoder_writeComment),
+        native!("xyz/cssxsh/gif/Encoder", "writeApplication", "(JLjava/lang/String;J)V", Java_xyz_cssxsh_gif_Encoder_writeApplication),
+        native!("xyz/cssxsh/gif/Encoder", "writeXMP", "(JLjava/lang/String;)V", Java_xyz_cssxsh_gif_Encoder_writeXMP),
+        native!("xyz/cssxsh/gif/Encoder", "close", "(J)V", Java_xyz_cssxsh_gif_Encoder_close),
+
+        native!("xyz/cssxsh/gif/Decoder", "file", "(Ljava/lang/String;)J", Java_xyz_cssxsh_gif_Decoder_file),
+        native!("xyz/cssxsh/gif/Decoder", "getSize", "(J)[I", Java_xyz_cssxsh_gif_Decoder_getSize),
+        native!("xyz/cssxsh/gif/Decoder", "getGlobalPalette", "(J)J", Java_xyz_cssxsh_gif_Decoder_getGlobalPalette),
+        native!("xyz/cssxsh/gif/Decoder", "getComments", "(J)[Ljava/lang/String;", Java_xyz_cssxsh_gif_Decoder_getComments),
+        native!("xyz/cssxsh/gif/Decoder", "getXMP", "(J)Ljava/lang/String;", Java_xyz_cssxsh_gif_Decoder_getXMP),
+        native!("xyz/cssxsh/gif/Decoder", "getApplicationIdentifiers", "(J)[Ljava/lang/String;", Java_xyz_cssxsh_gif_Decoder_getApplicationIdentifiers),
+        native!("xyz/cssxsh/gif/Decoder", "getApplication", "(JLjava/lang/String;)J", Java_xyz_cssxsh_gif_Decoder_getApplication),
+        native!("xyz/cssxsh/gif/Decoder", "readFrame", "(J)J", Java_xyz_cssxsh_gif_Decoder_readFrame),
+        native!("xyz/cssxsh/gif/Decoder", "close", "(J)V", Java_xyz_cssxsh_gif_Decoder_close),
+
+        native!("xyz/cssxsh/gif/Pipeline", "create", "(JII)J", Java_xyz_cssxsh_gif_Pipeline_create),
+        native!("xyz/cssxsh/gif/Pipeline", "writeImage", "(JJIIII)V", Java_xyz_cssxsh_gif_Pipeline_writeImage),
+        native!("xyz/cssxsh/gif/Pipeline", "writeBitmap", "(JJIIII)V", Java_xyz_cssxsh_gif_Pipeline_writeBitmap),
+        native!("xyz/cssxsh/gif/Pipeline", "finish", "(J)J", Java_xyz_cssxsh_gif_Pipeline_finish),
+        native!("xyz/cssxsh/gif/Pipeline", "close", "(J)V", Java_xyz_cssxsh_gif_Pipeline_close),
+
+        native!("xyz/cssxsh/gif/EncodeJob", "create", "(JIILxyz/cssxsh/gif/EncodeJob$Listener;)J", Java_xyz_cssxsh_gif_EncodeJob_create),
+        native!("xyz/cssxsh/gif/EncodeJob", "writeImage", "(JJIIII)V", Java_xyz_cssxsh_gif_EncodeJob_writeImage),
+        native!("xyz/cssxsh/gif/EncodeJob", "writeBitmap", "(JJIIII)V", Java_xyz_cssxsh_gif_EncodeJob_writeBitmap),
+        native!("xyz/cssxsh/gif/EncodeJob", "cancel", "(J)V", Java_xyz_cssxsh_gif_EncodeJob_cancel),
+        native!("xyz/cssxsh/gif/EncodeJob", "finish", "(J)V", Java_xyz_cssxsh_gif_EncodeJob_finish),
+
+        native!("xyz/cssxsh/gif/Budget", "create", "(IIJI)J", Java_xyz_cssxsh_gif_Budget_create),
+        native!("xyz/cssxsh/gif/Budget", "addImage", "(JJI)V", Java_xyz_cssxsh_gif_Budget_addImage),
+        native!("xyz/cssxsh/gif/Budget", "addBitmap", "(JJI)V", Java_xyz_cssxsh_gif_Budget_addBitmap),
+        native!("xyz/cssxsh/gif/Budget", "encode", "(J[I)J", Java_xyz_cssxsh_gif_Budget_encode),
+        native!("xyz/cssxsh/gif/Budget", "close", "(J)V", Java_xyz_cssxsh_gif_Budget_close),
+
+        native!("xyz/cssxsh/gif/Frame", "default", internal, "()J", Java_xyz_cssxsh_gif_Frame_default_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromIndexedPixels", internal, "(IIJI)J", Java_xyz_cssxsh_gif_Frame_fromIndexedPixels_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromIndexedPixelsArray", internal, "(II[BI)J", Java_xyz_cssxsh_gif_Frame_fromIndexedPixelsArray_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromIndexedPixelsBuffer", internal, "(IILjava/nio/ByteBuffer;I)J", Java_xyz_cssxsh_gif_Frame_fromIndexedPixelsBuffer_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromPalettePixels", internal, "(IIJJI)J", Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin__IIJJI),
+        native!("xyz/cssxsh/gif/Frame", "fromPalettePixels", internal, "(IIJJII)J", Java_xyz_cssxsh_gif_Frame_fromPalettePixels_00024mirai_1skia_1plugin__IIJJII),
+        native!("xyz/cssxsh/gif/Frame", "fromPalettePixelsArray", internal, "(II[B[BII)J", Java_xyz_cssxsh_gif_Frame_fromPalettePixelsArray_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromPalettePixelsBuffer", internal, "(IILjava/nio/ByteBuffer;Ljava/nio/ByteBuffer;II)J", Java_xyz_cssxsh_gif_Frame_fromPalettePixelsBuffer_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBSpeed", internal, "(IIJI)J", Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin__IIJI),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBSpeed", internal, "(IIJII)J", Java_xyz_cssxsh_gif_Frame_fromRGBSpeed_00024mirai_1skia_1plugin__IIJII),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBSpeedArray", internal, "(II[BII)J", Java_xyz_cssxsh_gif_Frame_fromRGBSpeedArray_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBSpeedBuffer", internal, "(IILjava/nio/ByteBuffer;II)J", Java_xyz_cssxsh_gif_Frame_fromRGBSpeedBuffer_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBASpeed", internal, "(IIJI)J", Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin__IIJI),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBASpeed", internal, "(IIJII)J", Java_xyz_cssxsh_gif_Frame_fromRGBASpeed_00024mirai_1skia_1plugin__IIJII),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBASpeedArray", internal, "(II[BII)J", Java_xyz_cssxsh_gif_Frame_fromRGBASpeedArray_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromRGBASpeedBuffer", internal, "(IILjava/nio/ByteBuffer;II)J", Java_xyz_cssxsh_gif_Frame_fromRGBASpeedBuffer_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "fromImage", internal, "(JI)J", Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin__JI),
+        native!("xyz/cssxsh/gif/Frame", "fromImage", internal, "(JII)J", Java_xyz_cssxsh_gif_Frame_fromImage_00024mirai_1skia_1plugin__JII),
+        native!("xyz/cssxsh/gif/Frame", "fromBitmap", internal, "(JI)J", Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin__JI),
+        native!("xyz/cssxsh/gif/Frame", "fromBitmap", internal, "(JII)J", Java_xyz_cssxsh_gif_Frame_fromBitmap_00024mirai_1skia_1plugin__JII),
+        native!("xyz/cssxsh/gif/Frame", "fromPixmap", internal, "(JI)J", Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin__JI),
+        native!("xyz/cssxsh/gif/Frame", "fromPixmap", internal, "(JII)J", Java_xyz_cssxsh_gif_Frame_fromPixmap_00024mirai_1skia_1plugin__JII),
+        native!("xyz/cssxsh/gif/Frame", "close", internal, "(J)V", Java_xyz_cssxsh_gif_Frame_close_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getDelay", internal, "(J)I", Java_xyz_cssxsh_gif_Frame_getDelay_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setDelay", internal, "(JI)V", Java_xyz_cssxsh_gif_Frame_setDelay_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getDispose", internal, "(J)I", Java_xyz_cssxsh_gif_Frame_getDispose_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setDispose", internal, "(JI)V", Java_xyz_cssxsh_gif_Frame_setDispose_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getRect", internal, "(J)[I", Java_xyz_cssxsh_gif_Frame_getRect_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setRect", internal, "(JIIII)V", Java_xyz_cssxsh_gif_Frame_setRect_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "copy", internal, "(J)J", Java_xyz_cssxsh_gif_Frame_copy_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "crop", internal, "(JIIII)V", Java_xyz_cssxsh_gif_Frame_crop_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "offset", internal, "(JII)V", Java_xyz_cssxsh_gif_Frame_offset_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getPalette", internal, "(J)J", Java_xyz_cssxsh_gif_Frame_getPalette_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setPalette", internal, "(JJ)V", Java_xyz_cssxsh_gif_Frame_setPalette_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getTransparent", internal, "(J)I", Java_xyz_cssxsh_gif_Frame_getTransparent_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setTransparent", internal, "(JI)V", Java_xyz_cssxsh_gif_Frame_setTransparent_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getInterlaced", internal, "(J)Z", Java_xyz_cssxsh_gif_Frame_getInterlaced_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setInterlaced", internal, "(JZ)V", Java_xyz_cssxsh_gif_Frame_setInterlaced_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getNeedsUserInput", internal, "(J)Z", Java_xyz_cssxsh_gif_Frame_getNeedsUserInput_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setNeedsUserInput", internal, "(JZ)V", Java_xyz_cssxsh_gif_Frame_setNeedsUserInput_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "getBuffer", internal, "(J)J", Java_xyz_cssxsh_gif_Frame_getBuffer_00024mirai_1skia_1plugin),
+        native!("xyz/cssxsh/gif/Frame", "setBuffer", internal, "(JJ)V", Java_xyz_cssxsh_gif_Frame_setBuffer_00024mirai_1skia_1plugin),
+
+        native!("xyz/cssxsh/skia/StyleUtils", "renderLowPoly", "(DIIIIJ)J", Java_xyz_cssxsh_skia_StyleUtils_renderLowPoly),
+    ]
+}
+
+/// Where the natives are bound, read from system properties set before `System.load`:
+/// `xyz.cssxsh.gif.package` and `xyz.cssxsh.skia.package` move the classes to another package
+/// (dotted or slashed), `xyz.cssxsh.gif.module` names the Kotlin module of `internal` members,
+/// empty for none.
+struct Binding {
+    packages: Vec<(&'static str, String)>,
+    module: String,
+}
+
+fn property(env: &JNIEnv, key: &str) -> Option<String> {
+    let result = (|| {
+        let key = env.new_string(key)?;
+        let value = env.call_static_method(
+            "java/lang/System",
+            "getProperty",
+            "(Ljava/lang/String;)Ljava/lang/String;",
+            &[JValue::Object(key.into())],
+        )?.l()?;
+        if value.is_null() {
+            return Ok(None);
+        }
+        let value: String = env.get_string(value.into())?.into();
+        Ok::<_, jni::errors::Error>(Some(value))
+    })();
+
+    result.unwrap_or_else(|_| {
+        let _ = env.exception_clear();
+        None
+    })
+}
+
+impl Binding {
+    fn load(env: &JNIEnv) -> Self {
+        let package = |key: &str, default: &'static str| {
+            let package = property(env, key)
+                .map(|package| package.replace('.', "/"))
+                .unwrap_or_else(|| default.to_owned());
+            (default, package)
+        };
+
+        Binding {
+            packages: vec![
+                package("xyz.cssxsh.gif.package", GIF_PACKAGE),
+                package("xyz.cssxsh.skia.package", SKIA_PACKAGE),
+            ],
+            module: property(env, "xyz.cssxsh.gif.module").unwrap_or_else(|| MODULE.to_owned()),
+        }
+    }
+
+    fn rename(&self, text: &str) -> String {
+        self.packages.iter()
+            .fold(text.to_owned(), |text, (from, to)| text.replace(&format!("{from}/"), &format!("{to}/")))
+    }
+
+    fn name(&self, native: &Native) -> String {
+        if native.internal && !self.module.is_empty() {
+            format!("{}${}", native.name, self.module)
+        } else {
+            native.name.to_owned()
+        }
+    }
+}
+
+/// Registers every native whose class and method exist, one at a time so that a class built without
+/// some of them still gets the rest. Returns how many were bound.
+fn register(env: &JNIEnv) -> usize {
+    let binding = Binding::load(env);
+    let mut classes: HashMap<&str, Option<GlobalRef>> = HashMap::new();
+    let mut count = 0;
+
+    for native in natives() {
+        let class = classes.entry(native.class).or_insert_with(|| {
+            let class = env.find_class(binding.rename(native.class)).ok()
+                .and_then(|class| env.new_global_ref(class).ok());
+            let _ = env.exception_clear();
+            class
+        });
+        let class = match class {
+            Some(class) => JClass::from(class.as_obj()),
+            None => continue,
+        };
+
+        let method = NativeMethod {
+            name: binding.name(&native).into(),
+            sig: binding.rename(native.sig).into(),
+            fn_ptr: native.fn_ptr,
+        };
+        match env.register_native_methods(class, &[method]) {
+            Ok(()) => count += 1,
+            Err(_) => {
+                let _ = env.exception_clear();
+            }
+        }
+    }
+
+    count
+}
+
+/// Symbols exported under the default names keep working, registration only adds the bindings
+/// for classes that live elsewhere.
+///
+/// # Safety
+///
+/// Called by the JVM with its own `JavaVM` pointer when the library is loaded.
+#[no_mangle]
+pub unsafe extern "system" fn JNI_OnLoad(vm: *mut jni::sys::JavaVM, _reserved: *mut c_void) -> jint {
+    if let Some(env) = JavaVM::from_raw(vm).ok().as_ref().and_then(|vm| vm.get_env().ok()) {
+        register(&env);
+    }
+
+    JNI_VERSION_1_6
+}
+
+#[test]
+fn binding_rename() {
+    let binding = Binding {
+        packages: vec![(GIF_PACKAGE, String::from("com/example/gif")), (SKIA_PACKAGE, String::from(SKIA_PACKAGE))],
+        module: String::new(),
+    };
+    let native = native!("xyz/cssxsh/gif/Frame", "getDelay", internal, "(J)I", JNI_OnLoad);
+
+    assert_eq!(binding.rename("xyz/cssxsh/gif/Quantizer$OctTree"), "com/example/gif/Quantizer$OctTree");
+    assert_eq!(binding.rename("(JLxyz/cssxsh/gif/EncodeJob$Listener;)J"), "(JLcom/example/gif/EncodeJob$Listener;)J");
+    assert_eq!(binding.rename("xyz/cssxsh/skia/StyleUtils"), "xyz/cssxsh/skia/StyleUtils");
+    assert_eq!(binding.name(&native), "getDelay");
+    assert_eq!(Binding { module: String::from(MODULE), ..binding }.name(&native), "getDelay$mirai_skia_plugin");
+}
@@ -0,0 +1,226 @@
+use std::borrow::Cow;
+use std::slice;
+
+type RGBA = [u8; 4];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Alpha8,
+    RGB565,
+    RGBA8888,
+    RGB888x,
+    BGRA8888,
+    Gray8,
+    RGBAF16,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 7] = [
+        Layout::Alpha8,
+        Layout::RGB565,
+        Layout::RGBA8888,
+        Layout::RGB888x,
+        Layout::BGRA8888,
+        Layout::Gray8,
+        Layout::RGBAF16,
+    ];
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Layout::Alpha8 | Layout::Gray8 => 1,
+            Layout::RGB565 => 2,
+            Layout::RGBA8888 | Layout::RGB888x | Layout::BGRA8888 => 4,
+            Layout::RGBAF16 => 8,
+        }
+    }
+}
+
+// region Pixel Decode
+
+fn unpremultiply([red, green, blue, alpha]: RGBA) -> RGBA {
+    match alpha {
+        0 => [0, 0, 0, 0],
+        255 => [red, green, blue, alpha],
+        _ => {
+            let scale = |value: u8| ((value as u32 * 255 + alpha as u32 / 2) / alpha as u32).min(255) as u8;
+            [scale(red), scale(green), scale(blue), alpha]
+        }
+    }
+}
+
+fn rgb565(pixel: &[u8]) -> RGBA {
+    let value = u16::from_ne_bytes([pixel[0], pixel[1]]);
+    let red = (value >> 11) as u8 & 0x1F;
+    let green = (value >> 5) as u8 & 0x3F;
+    let blue = value as u8 & 0x1F;
+
+    [red << 3 | red >> 2, green << 2 | green >> 4, blue << 3 | blue >> 2, 0xFF]
+}
+
+fn half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = (bits >> 10 & 0x1F) as i32;
+    let mantissa = (bits & 0x3FF) as f32;
+
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn unorm(value: f32) -> u8 {
+    // NaN falls through clamp and saturates to 0
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn rgba_f16(pixel: &[u8], premultiplied: bool) -> RGBA {
+    let channel = |index: usize| half(u16::from_ne_bytes([pixel[index * 2], pixel[index * 2 + 1]]));
+    let alpha = channel(3).clamp(0.0, 1.0);
+    // divide in float so low alpha keeps its precision
+    let scale = if premultiplied && alpha > 0.0 { alpha.recip() } else { 1.0 };
+
+    [unorm(channel(0) * scale), unorm(channel(1) * scale), unorm(channel(2) * scale), unorm(alpha)]
+}
+
+fn decoder(layout: Layout, premultiplied: bool) -> fn(&[u8]) -> RGBA {
+    match (layout, premultiplied) {
+        (Layout::Alpha8, _) => |pixel| [0, 0, 0, pixel[0]],
+        (Layout::RGB565, _) => rgb565,
+        (Layout::RGBA8888, false) => |pixel| [pixel[0], pixel[1], pixel[2], pixel[3]],
+        (Layout::RGBA8888, true) => |pixel| unpremultiply([pixel[0], pixel[1], pixel[2], pixel[3]]),
+        (Layout::RGB888x, _) => |pixel| [pixel[0], pixel[1], pixel[2], 0xFF],
+        (Layout::BGRA8888, false) => |pixel| [pixel[2], pixel[1], pixel[0], pixel[3]],
+        (Layout::BGRA8888, true) => |pixel| unpremultiply([pixel[2], pixel[1], pixel[0], pixel[3]]),
+        (Layout::Gray8, _) => |pixel| [pixel[0], pixel[0], pixel[0], 0xFF],
+        (Layout::RGBAF16, false) => |pixel| rgba_f16(pixel, false),
+        (Layout::RGBAF16, true) => |pixel| rgba_f16(pixel, true),
+    }
+}
+
+// endregion
+
+pub fn to_rgba(
+    bytes: &[u8],
+    layout: Layout,
+    premultiplied: bool,
+    width: usize,
+    height: usize,
+    row_bytes: usize,
+) -> Option<Cow<'_, [RGBA]>> {
+    let size = layout.bytes_per_pixel();
+    let line = width * size;
+    if row_bytes < line || height > 0 && bytes.len() < row_bytes * (height - 1) + line {
+        return None;
+    }
+
+    // tightly packed straight RGBA is what the encoder wants, so hand it out as is
+    if layout == Layout::RGBA8888 && (row_bytes == line || height <= 1) {
+        let pixels = unsafe { slice::from_raw_parts(bytes.as_ptr() as *const RGBA, width * height) };
+        if !premultiplied || pixels.iter().all(|pixel| pixel[3] == 0xFF) {
+            return Some(Cow::Borrowed(pixels));
+        }
+    }
+
+    let decode = decoder(layout, premultiplied);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &bytes[y * row_bytes..][..line];
+        pixels.extend(row.chunks_exact(size).map(decode));
+    }
+
+    Some(Cow::Owned(pixels))
+}
+
+#[test]
+fn rgba8888() {
+    let straight = [10, 20, 30, 255, 40, 50, 60, 128];
+    let pixels = to_rgba(&straight, Layout::RGBA8888, false, 2, 1, 8).unwrap();
+    assert!(matches!(pixels, Cow::Borrowed(_)));
+    assert_eq!(pixels.as_ref(), &[[10, 20, 30, 255], [40, 50, 60, 128]]);
+
+    let opaque = to_rgba(&[10, 20, 30, 255], Layout::RGBA8888, true, 1, 1, 4).unwrap();
+    assert!(matches!(opaque, Cow::Borrowed(_)));
+
+    let premultiplied = [64, 32, 0, 128, 0, 0, 0, 0];
+    let pixels = to_rgba(&premultiplied, Layout::RGBA8888, true, 2, 1, 8).unwrap();
+    assert_eq!(pixels.as_ref(), &[[128, 64, 0, 128], [0, 0, 0, 0]]);
+}
+
+#[test]
+fn row_stride() {
+    // 2x2 image with 4 bytes of padding after every row, last row unpadded
+    let bytes = [
+        1, 1, 1, 255, 2, 2, 2, 255, 9, 9, 9, 9,
+        3, 3, 3, 255, 4, 4, 4, 255,
+    ];
+    let pixels = to_rgba(&bytes, Layout::RGBA8888, false, 2, 2, 12).unwrap();
+    assert_eq!(pixels.as_ref(), &[[1, 1, 1, 255], [2, 2, 2, 255], [3, 3, 3, 255], [4, 4, 4, 255]]);
+
+    assert!(to_rgba(&bytes[..19], Layout::RGBA8888, false, 2, 2, 12).is_none());
+    assert!(to_rgba(&bytes, Layout::RGBA8888, false, 2, 2, 4).is_none());
+}
+
+#[test]
+fn rgb888x() {
+    let pixels = to_rgba(&[10, 20, 30, 0], Layout::RGB888x, true, 1, 1, 4).unwrap();
+    assert_eq!(pixels.as_ref(), &[[10, 20, 30, 255]]);
+}
+
+#[test]
+fn bgra8888() {
+    let bytes = [30, 20, 10, 255, 0, 32, 64, 128];
+    let pixels = to_rgba(&bytes, Layout::BGRA8888, false, 2, 1, 8).unwrap();
+    assert_eq!(pixels.as_ref(), &[[10, 20, 30, 255], [64, 32, 0, 128]]);
+
+    let pixels = to_rgba(&bytes, Layout::BGRA8888, true, 2, 1, 8).unwrap();
+    assert_eq!(pixels.as_ref(), &[[10, 20, 30, 255], [128, 64, 0, 128]]);
+}
+
+#[test]
+fn rgb565_expand() {
+    let white = 0xFFFFu16.to_ne_bytes();
+    let red = 0xF800u16.to_ne_bytes();
+    let green = 0x07E0u16.to_ne_bytes();
+    let gray = (0x10 << 11 | 0x20 << 5 | 0x10u16).to_ne_bytes();
+    let bytes = [white, red, green, gray].concat();
+
+    let pixels = to_rgba(&bytes, Layout::RGB565, false, 4, 1, 8).unwrap();
+    assert_eq!(pixels.as_ref(), &[[255, 255, 255, 255], [255, 0, 0, 255], [0, 255, 0, 255], [132, 130, 132, 255]]);
+}
+
+#[test]
+fn gray8() {
+    let pixels = to_rgba(&[0, 128, 7, 255, 7], Layout::Gray8, false, 2, 2, 3).unwrap();
+    assert_eq!(pixels.as_ref(), &[[0, 0, 0, 255], [128, 128, 128, 255], [255, 255, 255, 255], [7, 7, 7, 255]]);
+}
+
+#[test]
+fn alpha8() {
+    let pixels = to_rgba(&[0, 200], Layout::Alpha8, true, 2, 1, 2).unwrap();
+    assert_eq!(pixels.as_ref(), &[[0, 0, 0, 0], [0, 0, 0, 200]]);
+}
+
+#[test]
+fn rgba_f16_decode() {
+    // 1.0, 0.5, 0.0, 1.0 then premultiplied 0.25, 0.0, 2.0, 0.5
+    let straight = [0x3C00u16, 0x3800, 0x0000, 0x3C00];
+    let premultiplied = [0x3400u16, 0x0000, 0x4000, 0x3800];
+    let bytes: Vec<u8> = straight.iter()
+        .chain(premultiplied.iter())
+        .flat_map(|value| value.to_ne_bytes())
+        .collect();
+
+    let pixels = to_rgba(&bytes, Layout::RGBAF16, false, 2, 1, 16).unwrap();
+    assert_eq!(pixels[0], [255, 128, 0, 255]);
+    assert_eq!(pixels[1], [64, 0, 255, 128]);
+
+    let pixels = to_rgba(&bytes, Layout::RGBAF16, true, 2, 1, 16).unwrap();
+    assert_eq!(pixels[0], [255, 128, 0, 255]);
+    assert_eq!(pixels[1], [128, 0, 255, 128]);
+
+    assert_eq!(half(0x0001), 2f32.powi(-24));
+    assert_eq!(half(0xC000), -2.0);
+    assert!(half(0x7E00).is_nan());
+}
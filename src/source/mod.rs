@@ -0,0 +1,3 @@
+pub mod convert;
+pub mod reader;
+pub mod resize;
@@ -0,0 +1,192 @@
+use std::borrow::Cow;
+use std::sync::atomic::*;
+use skia_safe::*;
+use skia_safe::image::*;
+use super::convert::*;
+use super::resize::*;
+
+type RGBA = [u8; 4];
+
+static CONVERT_SRGB: AtomicBool = AtomicBool::new(true);
+
+pub fn convert_srgb() -> bool {
+    CONVERT_SRGB.load(Ordering::Relaxed)
+}
+
+pub fn set_convert_srgb(enable: bool) {
+    CONVERT_SRGB.store(enable, Ordering::Relaxed)
+}
+
+fn needs_srgb(image_info: &ImageInfo) -> bool {
+    // untagged pixels are taken as sRGB already
+    convert_srgb() && image_info.color_space().map_or(false, |color_space| !color_space.is_srgb())
+}
+
+fn layout(color_type: ColorType) -> Option<Layout> {
+    match color_type {
+        ColorType::Alpha8 => Some(Layout::Alpha8),
+        ColorType::RGB565 => Some(Layout::RGB565),
+        ColorType::RGBA8888 => Some(Layout::RGBA8888),
+        ColorType::RGB888x => Some(Layout::RGB888x),
+        ColorType::BGRA8888 => Some(Layout::BGRA8888),
+        ColorType::Gray8 => Some(Layout::Gray8),
+        ColorType::RGBAF16 | ColorType::RGBAF16Norm => Some(Layout::RGBAF16),
+        _ => None,
+    }
+}
+
+fn target_info(image_info: &ImageInfo) -> ImageInfo {
+    // opaque sources stay opaque, everything else is read back as straight alpha in sRGB
+    let alpha_type = match image_info.alpha_type() {
+        AlphaType::Opaque => AlphaType::Opaque,
+        _ => AlphaType::Unpremul,
+    };
+
+    let target = image_info.with_color_type(ColorType::RGBA8888)
+        .with_alpha_type(alpha_type);
+
+    if needs_srgb(image_info) {
+        target.with_color_space(ColorSpace::new_srgb())
+    } else {
+        target
+    }
+}
+
+pub fn pixmap_pixels(pixmap: &Pixmap) -> Option<Cow<'_, [RGBA]>> {
+    // wide gamut pixels go through skia's color transform instead of the raw decode
+    match layout(pixmap.color_type()).filter(|_| !needs_srgb(pixmap.info())) {
+        Some(layout) => {
+            to_rgba(
+                pixmap.bytes()?,
+                layout,
+                pixmap.alpha_type() == AlphaType::Premul,
+                pixmap.width() as usize,
+                pixmap.height() as usize,
+                pixmap.row_bytes(),
+            )
+        }
+        None => {
+            let image_info = target_info(pixmap.info());
+            let mut pixels = vec![[0u8; 4]; image_info.compute_min_byte_size() / 4];
+
+            let success = pixmap.read_pixels(
+                &image_info,
+                pixels.as_mut_slice(),
+                image_info.min_row_bytes(),
+                IPoint { x: 0, y: 0 },
+            );
+
+            success.then_some(Cow::Owned(pixels))
+        }
+    }
+}
+
+pub fn image_pixels<R>(image: &Image, block: impl FnOnce(&[RGBA]) -> R) -> Option<R> {
+    if let Some(pixmap) = image.peek_pixels() {
+        let pixels = pixmap_pixels(&pixmap)?;
+        return Some(block(&pixels));
+    }
+
+    // lazy or texture backed images have no pixels to peek, so let skia decode them
+    let image_info = target_info(image.image_info());
+    let mut pixels = vec![[0u8; 4]; image_info.compute_min_byte_size() / 4];
+
+    let success = image.read_pixels(
+        &image_info,
+        pixels.as_mut_slice(),
+        image_info.min_row_bytes(),
+        IPoint { x: 0, y: 0 },
+        CachingHint::Allow,
+    );
+
+    success.then(|| block(&pixels))
+}
+
+pub fn sampling_options(sampling: Sampling) -> SamplingOptions {
+    match sampling {
+        Sampling::Nearest => SamplingOptions::new(FilterMode::Nearest, MipmapMode::None),
+        Sampling::Linear => SamplingOptions::new(FilterMode::Linear, MipmapMode::Linear),
+        Sampling::Mitchell => CubicResampler::mitchell().into(),
+        Sampling::CatmullRom => CubicResampler::catmull_rom().into(),
+        // skia has no lanczos filter, resample_pixels runs its own kernel and only draws unscaled
+        Sampling::Lanczos => SamplingOptions::new(FilterMode::Nearest, MipmapMode::None),
+    }
+}
+
+fn lanczos_pixels<R>(image: &Image, placement: &Placement, color_space: Option<ColorSpace>, block: impl FnOnce(&[RGBA]) -> R) -> Option<R> {
+    let [_, _, frame_width, frame_height] = placement.frame;
+    let (width, height) = (image.width(), image.height());
+    let source_info = ImageInfo::new((width, height), ColorType::RGBA8888, AlphaType::Premul, color_space.clone());
+    let mut surface = Surface::new_raster(&source_info, None, None)?;
+
+    surface.canvas().draw_image(image, Point::new(0.0, 0.0), None);
+
+    let source: Vec<RGBA> = surface.peek_pixels()?
+        .bytes()?
+        .chunks_exact(4)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+        .collect();
+    let resampled = lanczos(
+        &source,
+        width as usize,
+        height as usize,
+        placement.crop,
+        (frame_width as usize, frame_height as usize),
+    );
+
+    let bytes = resampled.concat();
+    let frame_info = ImageInfo::new((frame_width as i32, frame_height as i32), ColorType::RGBA8888, AlphaType::Premul, color_space);
+    let pixmap = Pixmap::new(&frame_info, &bytes, frame_info.min_row_bytes());
+    let pixels = pixmap_pixels(&pixmap)?;
+    Some(block(&pixels))
+}
+
+pub fn resample_pixels<R>(
+    image: &Image,
+    placement: &Placement,
+    sampling: Sampling,
+    block: impl FnOnce(&[RGBA]) -> R,
+) -> Option<R> {
+    let [x, y, width, height] = placement.crop;
+    let [_, _, frame_width, frame_height] = placement.frame;
+    let color_space = if convert_srgb() {
+        Some(ColorSpace::new_srgb())
+    } else {
+        image.image_info().color_space()
+    };
+    if sampling == Sampling::Lanczos {
+        return lanczos_pixels(image, placement, color_space, block);
+    }
+    let image_info = ImageInfo::new(
+        (frame_width as i32, frame_height as i32),
+        ColorType::RGBA8888,
+        AlphaType::Premul,
+        color_space,
+    );
+    let mut surface = Surface::new_raster(&image_info, None, None)?;
+
+    surface.canvas().draw_image_rect_with_sampling_options(
+        image,
+        Some((&Rect::from_xywh(x, y, width, height), SrcRectConstraint::Fast)),
+        Rect::from_iwh(frame_width as _, frame_height as _),
+        sampling_options(sampling),
+        &Paint::default(),
+    );
+
+    let pixmap = surface.peek_pixels()?;
+    let pixels = pixmap_pixels(&pixmap)?;
+    Some(block(&pixels))
+}
+
+pub fn resample_rgba(pixels: &[RGBA], size: (usize, usize), target: (usize, usize)) -> Option<Vec<RGBA>> {
+    let (width, height) = size;
+    let (target_width, target_height) = target;
+    let image_info = ImageInfo::new((width as i32, height as i32), ColorType::RGBA8888, AlphaType::Unpremul, None);
+    let image = Image::from_raster_data(&image_info, Data::new_copy(&pixels.concat()), image_info.min_row_bytes())?;
+    let placement = Placement {
+        crop: [0.0, 0.0, width as f32, height as f32],
+        frame: [0, 0, target_width as u16, target_height as u16],
+    };
+
+    resample_pixels(&image, &placement, Sampling::Mitchell, |pixels| pixels.to_vec())
+}
@@ -0,0 +1,225 @@
+type RGBA = [u8; 4];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resize {
+    None = 0,
+    Fit = 1,
+    Fill = 2,
+    Exact = 3,
+}
+
+impl Resize {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Resize::None),
+            1 => Some(Resize::Fit),
+            2 => Some(Resize::Fill),
+            3 => Some(Resize::Exact),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    Nearest = 0,
+    Linear = 1,
+    Mitchell = 2,
+    CatmullRom = 3,
+    Lanczos = 4,
+}
+
+impl Sampling {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Sampling::Nearest),
+            1 => Some(Sampling::Linear),
+            2 => Some(Sampling::Mitchell),
+            3 => Some(Sampling::CatmullRom),
+            4 => Some(Sampling::Lanczos),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    /// source rect as x, y, width, height
+    pub crop: [f32; 4],
+    /// frame rect on the canvas as left, top, width, height
+    pub frame: [u16; 4],
+}
+
+pub fn placement(resize: Resize, source: (u32, u32), canvas: (u16, u16)) -> Option<Placement> {
+    let (width, height) = source;
+    let (canvas_width, canvas_height) = canvas;
+    if width == 0 || height == 0 || canvas_width == 0 || canvas_height == 0 {
+        return None;
+    }
+    if (width, height) == (canvas_width as u32, canvas_height as u32) {
+        return None;
+    }
+    let (width, height) = (width as f32, height as f32);
+    let (canvas_width, canvas_height) = (canvas_width as f32, canvas_height as f32);
+
+    match resize {
+        Resize::None => None,
+        Resize::Exact => Some(Placement {
+            crop: [0.0, 0.0, width, height],
+            frame: [0, 0, canvas_width as u16, canvas_height as u16],
+        }),
+        Resize::Fit => {
+            // keep the aspect ratio and center the smaller frame, the gif rect does the letterboxing
+            let scale = (canvas_width / width).min(canvas_height / height);
+            let frame_width = (width * scale).round().clamp(1.0, canvas_width);
+            let frame_height = (height * scale).round().clamp(1.0, canvas_height);
+
+            Some(Placement {
+                crop: [0.0, 0.0, width, height],
+                frame: [
+                    ((canvas_width - frame_width) / 2.0) as u16,
+                    ((canvas_height - frame_height) / 2.0) as u16,
+                    frame_width as u16,
+                    frame_height as u16,
+                ],
+            })
+        }
+        Resize::Fill => {
+            // cover the whole canvas and trim the overflow evenly from both sides
+            let scale = (canvas_width / width).max(canvas_height / height);
+            let crop_width = canvas_width / scale;
+            let crop_height = canvas_height / scale;
+
+            Some(Placement {
+                crop: [(width - crop_width) / 2.0, (height - crop_height) / 2.0, crop_width, crop_height],
+                frame: [0, 0, canvas_width as u16, canvas_height as u16],
+            })
+        }
+    }
+}
+
+const LANCZOS_LOBES: f32 = 3.0;
+
+fn lanczos_weight(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= LANCZOS_LOBES {
+        return 0.0;
+    }
+
+    let x = x * std::f32::consts::PI;
+    LANCZOS_LOBES * x.sin() * (x / LANCZOS_LOBES).sin() / (x * x)
+}
+
+fn lanczos_taps(offset: f32, length: f32, target: usize, limit: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = length / target as f32;
+    // when shrinking the kernel is stretched over the source so every pixel still contributes
+    let support = scale.max(1.0);
+
+    (0..target)
+        .map(|index| {
+            let center = offset + (index as f32 + 0.5) * scale - 0.5;
+            let start = (center - LANCZOS_LOBES * support).floor() as isize;
+            let end = (center + LANCZOS_LOBES * support).ceil() as isize;
+
+            let mut taps: Vec<(usize, f32)> = (start..=end)
+                .map(|position| {
+                    let source = position.clamp(0, limit as isize - 1) as usize;
+                    (source, lanczos_weight((position as f32 - center) / support))
+                })
+                .filter(|(_, weight)| *weight != 0.0)
+                .collect();
+
+            let total: f32 = taps.iter().map(|(_, weight)| weight).sum();
+            for (_, weight) in &mut taps {
+                *weight /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// Lanczos-3 resampling of the crop rect, the pixels are premultiplied so edges don't bleed color.
+pub fn lanczos(pixels: &[RGBA], width: usize, height: usize, crop: [f32; 4], frame: (usize, usize)) -> Vec<RGBA> {
+    let [x, y, crop_width, crop_height] = crop;
+    let (frame_width, frame_height) = frame;
+    if width == 0 || height == 0 || frame_width == 0 || frame_height == 0 {
+        return Vec::new();
+    }
+    let columns = lanczos_taps(x, crop_width, frame_width, width);
+    let rows = lanczos_taps(y, crop_height, frame_height, height);
+
+    let mut horizontal = vec![[0f32; 4]; frame_width * height];
+    for (row, line) in pixels.chunks_exact(width).take(height).enumerate() {
+        for (column, taps) in columns.iter().enumerate() {
+            let target = &mut horizontal[row * frame_width + column];
+            for (source, weight) in taps {
+                for (value, component) in target.iter_mut().zip(line[*source]) {
+                    *value += component as f32 * weight;
+                }
+            }
+        }
+    }
+
+    let mut resampled = Vec::with_capacity(frame_width * frame_height);
+    for taps in &rows {
+        for column in 0..frame_width {
+            let mut sum = [0f32; 4];
+            for (source, weight) in taps {
+                for (value, component) in sum.iter_mut().zip(horizontal[source * frame_width + column]) {
+                    *value += component * weight;
+                }
+            }
+
+            // the lobes overshoot around hard edges, premultiplied color can't exceed its alpha
+            let alpha = sum[3].round().clamp(0.0, 255.0);
+            resampled.push([
+                sum[0].round().clamp(0.0, alpha) as u8,
+                sum[1].round().clamp(0.0, alpha) as u8,
+                sum[2].round().clamp(0.0, alpha) as u8,
+                alpha as u8,
+            ]);
+        }
+    }
+
+    resampled
+}
+
+#[test]
+fn placement_modes() {
+    assert_eq!(placement(Resize::Fit, (400, 300), (400, 300)), None);
+    assert_eq!(placement(Resize::None, (800, 600), (400, 300)), None);
+    assert_eq!(placement(Resize::Fit, (800, 600), (0, 300)), None);
+    assert_eq!(placement(Resize::Fill, (800, 600), (400, 0)), None);
+
+    let exact = placement(Resize::Exact, (800, 400), (300, 300)).unwrap();
+    assert_eq!(exact.crop, [0.0, 0.0, 800.0, 400.0]);
+    assert_eq!(exact.frame, [0, 0, 300, 300]);
+
+    let fit = placement(Resize::Fit, (800, 400), (300, 300)).unwrap();
+    assert_eq!(fit.crop, [0.0, 0.0, 800.0, 400.0]);
+    assert_eq!(fit.frame, [0, 75, 300, 150]);
+
+    let fill = placement(Resize::Fill, (800, 400), (300, 300)).unwrap();
+    assert_eq!(fill.crop, [200.0, 0.0, 400.0, 400.0]);
+    assert_eq!(fill.frame, [0, 0, 300, 300]);
+
+    let upscale = placement(Resize::Fit, (10, 40), (100, 100)).unwrap();
+    assert_eq!(upscale.frame, [37, 0, 25, 100]);
+}
+
+#[test]
+fn lanczos_resample() {
+    let flat = vec![[40, 80, 120, 255]; 16 * 8];
+    assert_eq!(lanczos(&flat, 16, 8, [0.0, 0.0, 16.0, 8.0], (5, 3)), vec![[40, 80, 120, 255]; 5 * 3]);
+
+    let stripes: Vec<RGBA> = (0..64 * 4)
+        .map(|i| if i % 2 == 0 { [0, 0, 0, 255] } else { [200, 200, 200, 255] })
+        .collect();
+    let halved = lanczos(&stripes, 64, 4, [0.0, 0.0, 64.0, 4.0], (16, 1));
+    assert!(halved[4..12].iter().all(|pixel| pixel[0].abs_diff(100) <= 2 && pixel[3] == 255));
+
+    let crop = lanczos(&stripes, 64, 4, [1.0, 0.0, 1.0, 1.0], (1, 1));
+    assert_eq!(crop, vec![[200, 200, 200, 255]]);
+}